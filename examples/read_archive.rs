@@ -3,17 +3,18 @@ extern crate Archive;
 use Archive::*;
 
 fn main() {
-    let a = Reader::new().unwrap()
+    let mut a = Reader::new().unwrap()
         .support_filter_all()
         .support_format_all()
         .open_filename("archive.tar", 10240).unwrap();
 
-    loop {
-        match a.next_header() {
+    let mut entries = a.entries();
+    while let Some(entry) = entries.next_entry() {
+        match entry {
             Ok(e) => println!("{:?}", e.pathname()),
-            Err(_) => { break }
+            Err(err) => println!("error: {}", err)
         }
     }
 
     println!("the end");
-}
\ No newline at end of file
+}
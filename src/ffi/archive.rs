@@ -2,6 +2,7 @@
 extern crate libc;
 
 pub use self::libc::{c_void, c_int, c_uint, c_char, c_long, c_ulong, int64_t, ssize_t, wchar_t, size_t, time_t, FILE, stat, dev_t, mode_t};
+pub use self::libc::{SEEK_SET, SEEK_CUR, SEEK_END};
 
 #[repr(C)]
 pub struct Struct_archive;
@@ -91,43 +92,68 @@ pub const ARCHIVE_EXTRACT_HFS_COMPRESSION_FORCED: c_int = 0x8000;
 /* Default: Do not reject entries with absolute paths */
 pub const ARCHIVE_EXTRACT_SECURE_NOABSOLUTEPATHS: c_int = 0x10000;
 
+/* Format codes, as returned by archive_format(). */
+pub const ARCHIVE_FORMAT_BASE_MASK: c_int = 0xff0000;
+pub const ARCHIVE_FORMAT_CPIO: c_int = 0x10000;
+pub const ARCHIVE_FORMAT_CPIO_POSIX: c_int = ARCHIVE_FORMAT_CPIO | 1;
+pub const ARCHIVE_FORMAT_CPIO_BIN_LE: c_int = ARCHIVE_FORMAT_CPIO | 2;
+pub const ARCHIVE_FORMAT_CPIO_BIN_BE: c_int = ARCHIVE_FORMAT_CPIO | 3;
+pub const ARCHIVE_FORMAT_CPIO_SVR4_NOCRC: c_int = ARCHIVE_FORMAT_CPIO | 4;
+pub const ARCHIVE_FORMAT_CPIO_SVR4_CRC: c_int = ARCHIVE_FORMAT_CPIO | 5;
+pub const ARCHIVE_FORMAT_CPIO_AFIO_LARGE: c_int = ARCHIVE_FORMAT_CPIO | 6;
+pub const ARCHIVE_FORMAT_SHAR: c_int = 0x20000;
+pub const ARCHIVE_FORMAT_SHAR_BASE: c_int = ARCHIVE_FORMAT_SHAR | 1;
+pub const ARCHIVE_FORMAT_SHAR_DUMP: c_int = ARCHIVE_FORMAT_SHAR | 2;
+pub const ARCHIVE_FORMAT_TAR: c_int = 0x30000;
+pub const ARCHIVE_FORMAT_TAR_USTAR: c_int = ARCHIVE_FORMAT_TAR | 1;
+pub const ARCHIVE_FORMAT_TAR_PAX_INTERCHANGE: c_int = ARCHIVE_FORMAT_TAR | 2;
+pub const ARCHIVE_FORMAT_TAR_PAX_RESTRICTED: c_int = ARCHIVE_FORMAT_TAR | 3;
+pub const ARCHIVE_FORMAT_TAR_GNUTAR: c_int = ARCHIVE_FORMAT_TAR | 4;
+pub const ARCHIVE_FORMAT_ISO9660: c_int = 0x40000;
+pub const ARCHIVE_FORMAT_ISO9660_ROCKRIDGE: c_int = ARCHIVE_FORMAT_ISO9660 | 1;
+pub const ARCHIVE_FORMAT_ZIP: c_int = 0x50000;
+pub const ARCHIVE_FORMAT_EMPTY: c_int = 0x60000;
+pub const ARCHIVE_FORMAT_AR: c_int = 0x70000;
+pub const ARCHIVE_FORMAT_AR_GNU: c_int = ARCHIVE_FORMAT_AR | 1;
+pub const ARCHIVE_FORMAT_AR_BSD: c_int = ARCHIVE_FORMAT_AR | 2;
+pub const ARCHIVE_FORMAT_MTREE: c_int = 0x80000;
+pub const ARCHIVE_FORMAT_RAW: c_int = 0x90000;
+pub const ARCHIVE_FORMAT_XAR: c_int = 0xA0000;
+pub const ARCHIVE_FORMAT_LHA: c_int = 0xB0000;
+pub const ARCHIVE_FORMAT_CAB: c_int = 0xC0000;
+pub const ARCHIVE_FORMAT_RAR: c_int = 0xD0000;
+pub const ARCHIVE_FORMAT_7ZIP: c_int = 0xE0000;
+pub const ARCHIVE_FORMAT_WARC: c_int = 0xF0000;
+
+/* Filter codes, as returned by archive_filter_code(). */
+pub const ARCHIVE_FILTER_NONE: c_int = 0;
+pub const ARCHIVE_FILTER_GZIP: c_int = 1;
+pub const ARCHIVE_FILTER_BZIP2: c_int = 2;
+pub const ARCHIVE_FILTER_COMPRESS: c_int = 3;
+pub const ARCHIVE_FILTER_PROGRAM: c_int = 4;
+pub const ARCHIVE_FILTER_LZMA: c_int = 5;
+pub const ARCHIVE_FILTER_XZ: c_int = 6;
+pub const ARCHIVE_FILTER_UU: c_int = 7;
+pub const ARCHIVE_FILTER_RPM: c_int = 8;
+pub const ARCHIVE_FILTER_LZIP: c_int = 9;
+pub const ARCHIVE_FILTER_LRZIP: c_int = 10;
+pub const ARCHIVE_FILTER_LZOP: c_int = 11;
+pub const ARCHIVE_FILTER_GRZIP: c_int = 12;
+
+/* File type bits of archive_entry_filetype() / archive_entry_mode(). */
+pub const AE_IFMT:   c_uint = 0o170000;
+pub const AE_IFREG:  c_uint = 0o100000;
+pub const AE_IFLNK:  c_uint = 0o120000;
+pub const AE_IFSOCK: c_uint = 0o140000;
+pub const AE_IFCHR:  c_uint = 0o020000;
+pub const AE_IFBLK:  c_uint = 0o060000;
+pub const AE_IFDIR:  c_uint = 0o040000;
+pub const AE_IFIFO:  c_uint = 0o010000;
+
 extern "C" {
      pub fn archive_version_number() -> c_int;
      pub fn archive_version_string() -> *const c_char;
      pub fn archive_read_new() -> *mut Struct_archive;
-     pub fn archive_read_support_compression_all(arg1: *mut Struct_archive)
-     -> c_int;
-     pub fn archive_read_support_compression_bzip2(arg1: *mut Struct_archive)
-     -> c_int;
-     pub fn archive_read_support_compression_compress(arg1:
-                                                         *mut Struct_archive)
-     -> c_int;
-     pub fn archive_read_support_compression_gzip(arg1: *mut Struct_archive)
-     -> c_int;
-     pub fn archive_read_support_compression_lzip(arg1: *mut Struct_archive)
-     -> c_int;
-     pub fn archive_read_support_compression_lzma(arg1: *mut Struct_archive)
-     -> c_int;
-     pub fn archive_read_support_compression_none(arg1: *mut Struct_archive)
-     -> c_int;
-     pub fn archive_read_support_compression_program(arg1: *mut Struct_archive,
-                                                    command:
-                                                        *const c_char)
-     -> c_int;
-     pub fn archive_read_support_compression_program_signature(arg1:
-                                                                  *mut Struct_archive,
-                                                              arg2:
-                                                                  *const c_char,
-                                                              arg3:
-                                                                  *const c_void,
-                                                              arg4: size_t)
-     -> c_int;
-     pub fn archive_read_support_compression_rpm(arg1: *mut Struct_archive)
-     -> c_int;
-     pub fn archive_read_support_compression_uu(arg1: *mut Struct_archive)
-     -> c_int;
-     pub fn archive_read_support_compression_xz(arg1: *mut Struct_archive)
-     -> c_int;
      pub fn archive_read_support_filter_all(arg1: *mut Struct_archive)
      -> c_int;
      pub fn archive_read_support_filter_bzip2(arg1: *mut Struct_archive)
@@ -194,6 +220,8 @@ extern "C" {
      -> c_int;
      pub fn archive_read_support_format_tar(arg1: *mut Struct_archive)
      -> c_int;
+     pub fn archive_read_support_format_warc(arg1: *mut Struct_archive)
+     -> c_int;
      pub fn archive_read_support_format_xar(arg1: *mut Struct_archive)
      -> c_int;
      pub fn archive_read_support_format_zip(arg1: *mut Struct_archive)
@@ -214,40 +242,22 @@ extern "C" {
                                                         arg4: size_t)
      -> c_int;
      pub fn archive_read_set_open_callback(arg1: *mut Struct_archive,
-                                          arg2:
-                                              *mut ::std::option::Option<extern "C" fn()
-                                                                             ->
-                                                                                 c_int>)
+                                          arg2: archive_open_callback)
      -> c_int;
      pub fn archive_read_set_read_callback(arg1: *mut Struct_archive,
-                                          arg2:
-                                              *mut ::std::option::Option<extern "C" fn()
-                                                                             ->
-                                                                                 ssize_t>)
+                                          arg2: archive_read_callback)
      -> c_int;
      pub fn archive_read_set_seek_callback(arg1: *mut Struct_archive,
-                                          arg2:
-                                              *mut ::std::option::Option<extern "C" fn()
-                                                                             ->
-                                                                                 int64_t>)
+                                          arg2: archive_seek_callback)
      -> c_int;
      pub fn archive_read_set_skip_callback(arg1: *mut Struct_archive,
-                                          arg2:
-                                              *mut ::std::option::Option<extern "C" fn()
-                                                                             ->
-                                                                                 int64_t>)
+                                          arg2: archive_skip_callback)
      -> c_int;
      pub fn archive_read_set_close_callback(arg1: *mut Struct_archive,
-                                           arg2:
-                                               *mut ::std::option::Option<extern "C" fn()
-                                                                              ->
-                                                                                  c_int>)
+                                           arg2: archive_close_callback)
      -> c_int;
      pub fn archive_read_set_switch_callback(arg1: *mut Struct_archive,
-                                            arg2:
-                                                *mut ::std::option::Option<extern "C" fn()
-                                                                               ->
-                                                                                   c_int>)
+                                            arg2: archive_switch_callback)
      -> c_int;
      pub fn archive_read_set_callback_data(arg1: *mut Struct_archive,
                                           arg2: *mut c_void)
@@ -402,23 +412,6 @@ extern "C" {
      pub fn archive_write_set_skip_file(arg1: *mut Struct_archive,
                                        arg2: int64_t, arg3: int64_t)
      -> c_int;
-     pub fn archive_write_set_compression_bzip2(arg1: *mut Struct_archive)
-     -> c_int;
-     pub fn archive_write_set_compression_compress(arg1: *mut Struct_archive)
-     -> c_int;
-     pub fn archive_write_set_compression_gzip(arg1: *mut Struct_archive)
-     -> c_int;
-     pub fn archive_write_set_compression_lzip(arg1: *mut Struct_archive)
-     -> c_int;
-     pub fn archive_write_set_compression_lzma(arg1: *mut Struct_archive)
-     -> c_int;
-     pub fn archive_write_set_compression_none(arg1: *mut Struct_archive)
-     -> c_int;
-     pub fn archive_write_set_compression_program(arg1: *mut Struct_archive,
-                                                 cmd: *const c_char)
-     -> c_int;
-     pub fn archive_write_set_compression_xz(arg1: *mut Struct_archive)
-     -> c_int;
      pub fn archive_write_add_filter(arg1: *mut Struct_archive,
                                     filter_code: c_int)
      -> c_int;
@@ -480,6 +473,8 @@ extern "C" {
      -> c_int;
      pub fn archive_write_set_format_pax_restricted(arg1: *mut Struct_archive)
      -> c_int;
+     pub fn archive_write_set_format_raw(arg1: *mut Struct_archive)
+     -> c_int;
      pub fn archive_write_set_format_shar(arg1: *mut Struct_archive)
      -> c_int;
      pub fn archive_write_set_format_shar_dump(arg1: *mut Struct_archive)
@@ -488,6 +483,8 @@ extern "C" {
      -> c_int;
      pub fn archive_write_set_format_v7tar(arg1: *mut Struct_archive)
      -> c_int;
+     pub fn archive_write_set_format_warc(arg1: *mut Struct_archive)
+     -> c_int;
      pub fn archive_write_set_format_xar(arg1: *mut Struct_archive)
      -> c_int;
      pub fn archive_write_set_format_zip(arg1: *mut Struct_archive)
@@ -499,17 +496,9 @@ extern "C" {
      -> c_int;
      pub fn archive_write_open(arg1: *mut Struct_archive,
                               arg2: *mut c_void,
-                              arg3:
-                                  *mut ::std::option::Option<extern "C" fn()
-                                                                 ->
-                                                                     c_int>,
-                              arg4:
-                                  *mut ::std::option::Option<extern "C" fn()
-                                                                 -> ssize_t>,
-                              arg5:
-                                  *mut ::std::option::Option<extern "C" fn()
-                                                                 ->
-                                                                     c_int>)
+                              arg3: Option<archive_open_callback>,
+                              arg4: archive_write_callback,
+                              arg5: Option<archive_close_callback>)
      -> c_int;
      pub fn archive_write_open_fd(arg1: *mut Struct_archive,
                                  _fd: c_int) -> c_int;
@@ -714,12 +703,6 @@ extern "C" {
      -> c_int;
      pub fn archive_filter_name(arg1: *mut Struct_archive, arg2: c_int)
      -> *const c_char;
-     pub fn archive_position_compressed(arg1: *mut Struct_archive) -> int64_t;
-     pub fn archive_position_uncompressed(arg1: *mut Struct_archive)
-     -> int64_t;
-     pub fn archive_compression_name(arg1: *mut Struct_archive)
-     -> *const c_char;
-     pub fn archive_compression(arg1: *mut Struct_archive) -> c_int;
      pub fn archive_errno(arg1: *mut Struct_archive) -> c_int;
      pub fn archive_error_string(arg1: *mut Struct_archive)
      -> *const c_char;
@@ -873,10 +856,14 @@ extern "C" {
      -> *const c_char;
      pub fn archive_entry_gname_w(arg1: *mut Struct_archive_entry)
      -> *const wchar_t;
+     pub fn archive_entry_gname_utf8(arg1: *mut Struct_archive_entry)
+     -> *const c_char;
      pub fn archive_entry_hardlink(arg1: *mut Struct_archive_entry)
      -> *const c_char;
      pub fn archive_entry_hardlink_w(arg1: *mut Struct_archive_entry)
      -> *const wchar_t;
+     pub fn archive_entry_hardlink_utf8(arg1: *mut Struct_archive_entry)
+     -> *const c_char;
      pub fn archive_entry_ino(arg1: *mut Struct_archive_entry) -> int64_t;
      pub fn archive_entry_ino64(arg1: *mut Struct_archive_entry) -> int64_t;
      pub fn archive_entry_ino_is_set(arg1: *mut Struct_archive_entry)
@@ -893,6 +880,8 @@ extern "C" {
      -> *const c_char;
      pub fn archive_entry_pathname_w(arg1: *mut Struct_archive_entry)
      -> *const wchar_t;
+     pub fn archive_entry_pathname_utf8(arg1: *mut Struct_archive_entry)
+     -> *const c_char;
      pub fn archive_entry_perm(arg1: *mut Struct_archive_entry) -> mode_t;
      pub fn archive_entry_rdev(arg1: *mut Struct_archive_entry) -> dev_t;
      pub fn archive_entry_rdevmajor(arg1: *mut Struct_archive_entry) -> dev_t;
@@ -910,11 +899,15 @@ extern "C" {
      -> *const c_char;
      pub fn archive_entry_symlink_w(arg1: *mut Struct_archive_entry)
      -> *const wchar_t;
+     pub fn archive_entry_symlink_utf8(arg1: *mut Struct_archive_entry)
+     -> *const c_char;
      pub fn archive_entry_uid(arg1: *mut Struct_archive_entry) -> int64_t;
      pub fn archive_entry_uname(arg1: *mut Struct_archive_entry)
      -> *const c_char;
      pub fn archive_entry_uname_w(arg1: *mut Struct_archive_entry)
      -> *const wchar_t;
+     pub fn archive_entry_uname_utf8(arg1: *mut Struct_archive_entry)
+     -> *const c_char;
      pub fn archive_entry_set_atime(arg1: *mut Struct_archive_entry,
                                    arg2: time_t, arg3: c_long) -> ();
      pub fn archive_entry_unset_atime(arg1: *mut Struct_archive_entry) -> ();
@@ -17,32 +17,68 @@ use ffi::archive::*;
 use std::ptr;
 use std::ffi::CString;
 use std::ffi::CStr;
+use std::ffi::OsString;
+use std::path::PathBuf;
 use std::rc::Rc;
-use std::io::{Read, Seek};
+use std::io::{Read, Seek, Write};
+use std::cell::{RefCell, UnsafeCell};
+use std::sync::Arc;
 use std::error::Error;
 use std::any::Any;
+use std::fmt;
+use std::marker::PhantomData;
 
 
 extern crate time;
 use time::Timespec;
 
 
-#[allow(raw_pointer_derive)]
-#[derive(PartialEq, Clone)]
 pub struct Reader {
-    handler: Rc<*mut Struct_archive>
+    handler: Rc<*mut Struct_archive>,
+    policy: WarningPolicy,
+    warnings: Vec<ArchiveError>
 }
 
-#[derive(Debug)]
-pub struct AllocationError;
-#[derive(Debug)]
-pub enum ArchiveError {
+impl PartialEq for Reader {
+    fn eq(&self, other: &Reader) -> bool {
+        self.handler == other.handler
+    }
+}
+
+/// Status code returned by a libarchive call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveErrorCode {
     Ok,
     Warn,
     Failed,
     Retry,
     Eof,
-    Fatal
+    Fatal,
+    Unknown(i32)
+}
+
+/// Error reported by libarchive.
+///
+/// Besides the status code it keeps `archive_errno` and `archive_error_string`
+/// as they were on the handle at the moment of failure.
+#[derive(Debug, Clone)]
+pub struct ArchiveError {
+    code: ArchiveErrorCode,
+    errno: i32,
+    message: String,
+    cause: Option<Arc<std::io::Error>>
+}
+
+/// How `ARCHIVE_WARN` results are treated.
+///
+/// libarchive reports `ARCHIVE_WARN` when an operation succeeded but something
+/// was lost or ignored on the way (unknown pax keywords, failed uname lookups...).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarningPolicy {
+    /// Succeed and attach the warning to the returned value (the default).
+    Collect,
+    /// Treat warnings as errors.
+    Escalate
 }
 #[derive(Debug)]
 pub enum ArchiveExtractFlag {
@@ -65,70 +101,434 @@ pub enum ArchiveExtractFlag {
     Secure_Noabsolutepaths
 }
 
+/// Archive format, for both `Reader` and `Writer`. Not every format can be
+/// both read and written, see `can_read` and `can_write`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ArchiveFormat {
     _7Zip,
     Ar_Bsd,
     Ar_Svr4,
+    Cab,
     Cpio,
     Cpio_newc,
+    Empty,
     Gnutar,
-    Iso9600,
+    Iso9660,
+    Lha,
     Mtree,
-    // Mtree_Classic,
+    Mtree_Classic,
     Pax,
     Pax_Restricted,
+    Rar,
+    Raw,
     Shar,
     Shar_Dump,
+    Tar,
     Ustar,
-    // V7tar,
+    V7tar,
+    Warc,
     Xar,
     Zip
 }
 
+impl ArchiveFormat {
+    /// libarchive `ARCHIVE_FORMAT_*` code of the format.
+    pub fn code(&self) -> i32 {
+        match *self {
+            ArchiveFormat::_7Zip => ARCHIVE_FORMAT_7ZIP,
+            ArchiveFormat::Ar_Bsd => ARCHIVE_FORMAT_AR_BSD,
+            ArchiveFormat::Ar_Svr4 => ARCHIVE_FORMAT_AR_GNU,
+            ArchiveFormat::Cab => ARCHIVE_FORMAT_CAB,
+            ArchiveFormat::Cpio => ARCHIVE_FORMAT_CPIO_POSIX,
+            ArchiveFormat::Cpio_newc => ARCHIVE_FORMAT_CPIO_SVR4_NOCRC,
+            ArchiveFormat::Empty => ARCHIVE_FORMAT_EMPTY,
+            ArchiveFormat::Gnutar => ARCHIVE_FORMAT_TAR_GNUTAR,
+            ArchiveFormat::Iso9660 => ARCHIVE_FORMAT_ISO9660,
+            ArchiveFormat::Lha => ARCHIVE_FORMAT_LHA,
+            ArchiveFormat::Mtree | ArchiveFormat::Mtree_Classic => ARCHIVE_FORMAT_MTREE,
+            ArchiveFormat::Pax => ARCHIVE_FORMAT_TAR_PAX_INTERCHANGE,
+            ArchiveFormat::Pax_Restricted => ARCHIVE_FORMAT_TAR_PAX_RESTRICTED,
+            ArchiveFormat::Rar => ARCHIVE_FORMAT_RAR,
+            ArchiveFormat::Raw => ARCHIVE_FORMAT_RAW,
+            ArchiveFormat::Shar => ARCHIVE_FORMAT_SHAR_BASE,
+            ArchiveFormat::Shar_Dump => ARCHIVE_FORMAT_SHAR_DUMP,
+            ArchiveFormat::Tar => ARCHIVE_FORMAT_TAR,
+            ArchiveFormat::Ustar => ARCHIVE_FORMAT_TAR_USTAR,
+            ArchiveFormat::V7tar => ARCHIVE_FORMAT_TAR,
+            ArchiveFormat::Warc => ARCHIVE_FORMAT_WARC,
+            ArchiveFormat::Xar => ARCHIVE_FORMAT_XAR,
+            ArchiveFormat::Zip => ARCHIVE_FORMAT_ZIP
+        }
+    }
+
+    /// Whether libarchive has a reader for the format. Tar and ar variants are
+    /// all handled by the same reader.
+    pub fn can_read(&self) -> bool {
+        match *self {
+            ArchiveFormat::Shar | ArchiveFormat::Shar_Dump => false,
+            _ => true
+        }
+    }
+
+    /// Whether libarchive has a writer for the format.
+    pub fn can_write(&self) -> bool {
+        match *self {
+            ArchiveFormat::Cab | ArchiveFormat::Empty |
+            ArchiveFormat::Lha | ArchiveFormat::Rar => false,
+            _ => true
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ArchiveFilter {
+  /// Base64 encoding, framed like `uuencode -m`.
+  B64encode,
+  Bzip2,
+  Compress,
+  /// Requires the `grzip` program.
+  Grzip,
+  Gzip,
+  /// Requires the `lrzip` program.
+  Lrzip,
+  Lzip,
+  Lzma,
+  /// Uses liblzo2 when available, the `lzop` program otherwise.
+  Lzop,
+  None,
+  /// External program reading data on stdin and writing the filtered data on
+  /// stdout, e.g. `zstd -c`.
+  Program { command: String },
+  Uuencode,
+  Xz,
+  /// More filters may be added, don't match exhaustively.
+  #[doc(hidden)]
+  __Nonexhaustive
+}
+
+impl ArchiveFilter {
+  /// Module name libarchive uses for options of this filter.
+  fn module_name(&self) -> &'static str {
+    match *self {
+      ArchiveFilter::B64encode => "b64encode",
+      ArchiveFilter::Bzip2 => "bzip2",
+      ArchiveFilter::Compress => "compress",
+      ArchiveFilter::Grzip => "grzip",
+      ArchiveFilter::Gzip => "gzip",
+      ArchiveFilter::Lrzip => "lrzip",
+      ArchiveFilter::Lzip => "lzip",
+      ArchiveFilter::Lzma => "lzma",
+      ArchiveFilter::Lzop => "lzop",
+      ArchiveFilter::None => "none",
+      ArchiveFilter::Program { .. } => "program",
+      ArchiveFilter::Uuencode => "uuencode",
+      ArchiveFilter::Xz => "xz",
+      ArchiveFilter::__Nonexhaustive => ""
+    }
+  }
+}
+
+/// Tuning of a write filter, see `Writer::add_filter_with`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterOption {
+  /// Compression level, 0 to 9 (gzip, bzip2, xz, lzma, lzip).
+  CompressionLevel(u32),
+  /// Number of compression threads, 0 for one per CPU (xz).
+  Threads(u32),
+  /// Whether to store the modification time in the header, on by default;
+  /// turn it off for reproducible output (gzip).
+  Timestamp(bool)
+}
+
+impl FilterOption {
+  fn key_value(&self) -> (&'static str, Option<String>) {
+    match *self {
+      FilterOption::CompressionLevel(level) => ("compression-level", Some(level.to_string())),
+      FilterOption::Threads(threads) => ("threads", Some(threads.to_string())),
+      FilterOption::Timestamp(true) => ("timestamp", Some(String::from("1"))),
+      FilterOption::Timestamp(false) => ("timestamp", None)
+    }
+  }
+}
+
+/// Decompression filter that a `Reader` can be allowed to use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadFilter {
   Bzip2,
   Compress,
+  Grzip,
   Gzip,
+  Lrzip,
   Lzip,
   Lzma,
+  Lzop,
   None,
-  // TODO : Program(&str)
+  /// External decompression program, e.g. `zstd -d`. With a signature it is only
+  /// used for data starting with those bytes, without one it is always used.
+  Program { command: String, signature: Option<Vec<u8>> },
+  Rpm,
+  Uu,
   Xz
 }
 
-pub enum ArchiveEntryFiletype {
-  AE_IFMT  ,
-  AE_IFREG ,
-  AE_IFLNK ,
-  AE_IFSOCK,
-  AE_IFCHR ,
-  AE_IFBLK ,
-  AE_IFDIR ,
-  AE_IFIFO 
+/// Kind of an archive entry, decoded from the `AE_IF*` bits of its mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+  RegularFile,
+  Directory,
+  Symlink,
+  Socket,
+  CharDevice,
+  BlockDevice,
+  Fifo,
+  /// Type bits not known to this crate, or 0 when the entry has no type set.
+  Unknown(u32)
 }
-/*
-impl fmt::Debug for AllocationError {
-    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        fmt.debug_struct("AllocationError").finish()
+
+impl FileType {
+  /// Decodes the type bits of `mode`, permission bits are ignored.
+  pub fn from_mode(mode: u32) -> FileType {
+    match mode & AE_IFMT {
+      AE_IFREG  => FileType::RegularFile,
+      AE_IFDIR  => FileType::Directory,
+      AE_IFLNK  => FileType::Symlink,
+      AE_IFSOCK => FileType::Socket,
+      AE_IFCHR  => FileType::CharDevice,
+      AE_IFBLK  => FileType::BlockDevice,
+      AE_IFIFO  => FileType::Fifo,
+      other     => FileType::Unknown(other)
+    }
+  }
+
+  /// Type bits as stored in the mode.
+  pub fn to_mode(&self) -> u32 {
+    match *self {
+      FileType::RegularFile => AE_IFREG,
+      FileType::Directory   => AE_IFDIR,
+      FileType::Symlink     => AE_IFLNK,
+      FileType::Socket      => AE_IFSOCK,
+      FileType::CharDevice  => AE_IFCHR,
+      FileType::BlockDevice => AE_IFBLK,
+      FileType::Fifo        => AE_IFIFO,
+      FileType::Unknown(bits) => bits & AE_IFMT
     }
+  }
+
+  pub fn is_dir(&self) -> bool {
+    *self == FileType::Directory
+  }
+
+  pub fn is_file(&self) -> bool {
+    *self == FileType::RegularFile
+  }
+
+  pub fn is_symlink(&self) -> bool {
+    *self == FileType::Symlink
+  }
 }
 
-impl fmt::Debug for AllocationError {
-    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-                fmt.debug_struct("AllocationError").finish()
+impl From<std::fs::FileType> for FileType {
+  /// Outside unix only directories, regular files and symlinks can be told apart,
+  /// anything else maps to `Unknown(0)`.
+  fn from(filetype: std::fs::FileType) -> FileType {
+    if filetype.is_dir() {
+      FileType::Directory
+    } else if filetype.is_file() {
+      FileType::RegularFile
+    } else if filetype.is_symlink() {
+      FileType::Symlink
+    } else {
+      special_file_type(filetype)
     }
-}*/
+  }
+}
+
+#[cfg(unix)]
+fn special_file_type(filetype: std::fs::FileType) -> FileType {
+  use std::os::unix::fs::FileTypeExt;
+  if filetype.is_socket() {
+    FileType::Socket
+  } else if filetype.is_char_device() {
+    FileType::CharDevice
+  } else if filetype.is_block_device() {
+    FileType::BlockDevice
+  } else if filetype.is_fifo() {
+    FileType::Fifo
+  } else {
+    FileType::Unknown(0)
+  }
+}
+
+#[cfg(not(unix))]
+fn special_file_type(_: std::fs::FileType) -> FileType {
+  FileType::Unknown(0)
+}
 
 
-fn code_to_error(code: c_int) -> ArchiveError {
+fn code_to_error(code: c_int) -> ArchiveErrorCode {
     match code {
-        ARCHIVE_OK => { return ArchiveError::Ok; }
-        ARCHIVE_WARN => { return ArchiveError::Warn; }
-        ARCHIVE_FAILED => { return ArchiveError::Failed; }
-        ARCHIVE_RETRY => { return ArchiveError::Retry; }
-        ARCHIVE_EOF => { return ArchiveError::Eof; }
-        ARCHIVE_FATAL => { return ArchiveError::Fatal; }
-        _ => { panic!(); }
+        ARCHIVE_OK => { return ArchiveErrorCode::Ok; }
+        ARCHIVE_WARN => { return ArchiveErrorCode::Warn; }
+        ARCHIVE_FAILED => { return ArchiveErrorCode::Failed; }
+        ARCHIVE_RETRY => { return ArchiveErrorCode::Retry; }
+        ARCHIVE_EOF => { return ArchiveErrorCode::Eof; }
+        ARCHIVE_FATAL => { return ArchiveErrorCode::Fatal; }
+        _ => { return ArchiveErrorCode::Unknown(code); }
+    }
+}
+
+unsafe fn error_string(handler: *mut Struct_archive) -> String {
+    let message = archive_error_string(handler);
+    if message.is_null() {
+        String::new()
+    } else {
+        String::from_utf8_lossy(CStr::from_ptr(message).to_bytes()).into_owned()
+    }
+}
+
+impl ArchiveError {
+    /// Captures the error state of `handler` after a call returned `code`.
+    unsafe fn from_archive(handler: *mut Struct_archive, code: c_int) -> ArchiveError {
+        ArchiveError {
+            code: code_to_error(code),
+            errno: archive_errno(handler),
+            message: error_string(handler),
+            cause: None
+        }
+    }
+
+    /// Error raised by this crate rather than by libarchive.
+    fn custom(message: &str) -> ArchiveError {
+        ArchiveError {
+            code: ArchiveErrorCode::Fatal,
+            errno: 0,
+            message: String::from(message),
+            cause: None
+        }
+    }
+
+    fn allocation() -> ArchiveError {
+        ArchiveError::custom("failed to allocate libarchive handle")
+    }
+
+    fn with_cause(mut self, cause: std::io::Error) -> ArchiveError {
+        self.cause = Some(Arc::new(cause));
+        self
+    }
+
+    pub fn code(&self) -> ArchiveErrorCode {
+        self.code
+    }
+
+    pub fn is_warning(&self) -> bool {
+        self.code == ArchiveErrorCode::Warn
+    }
+
+    /// Value of `archive_errno` at the moment of failure.
+    pub fn errno(&self) -> i32 {
+        self.errno
+    }
+
+    /// Value of `archive_error_string` at the moment of failure, empty if libarchive set none.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// I/O error of the Rust stream behind the archive that caused this error, if any.
+    pub fn io_error(&self) -> Option<&std::io::Error> {
+        self.cause.as_ref().map(|err| &**err)
+    }
+}
+
+/// Maps a libarchive status to `Ok(None)` on success, `Ok(Some(warning))` on a
+/// warning allowed by `policy`, and `Err` otherwise.
+unsafe fn check_result(handler: *mut Struct_archive, res: c_int, policy: WarningPolicy) -> Result<Option<ArchiveError>, ArchiveError> {
+    if res==ARCHIVE_OK {
+        Ok(None)
+    } else if res==ARCHIVE_WARN && policy==WarningPolicy::Collect {
+        Ok(Some(ArchiveError::from_archive(handler, res)))
+    } else {
+        Err(ArchiveError::from_archive(handler, res))
+    }
+}
+
+/// Option setters report unknown modules or keys as `ARCHIVE_FAILED`, and the
+/// string forms return `ARCHIVE_WARN` when only some of the options were accepted.
+/// Any status but `ARCHIVE_OK` is an error here whatever the warning policy.
+unsafe fn check_option(handler: *mut Struct_archive, res: c_int) -> Result<(), ArchiveError> {
+    if res==ARCHIVE_OK {
+        Ok(())
+    } else {
+        Err(ArchiveError::from_archive(handler, res))
+    }
+}
+
+/// Converts a string argument for libarchive, failing on interior NUL bytes.
+fn to_cstring(s: &str) -> Result<CString, ArchiveError> {
+    CString::new(s).map_err(|_| ArchiveError::custom(&format!("{:?} contains a NUL byte", s)))
+}
+
+type OptionSetter = unsafe extern "C" fn(*mut Struct_archive, *const c_char, *const c_char, *const c_char) -> c_int;
+
+/// A `None` value turns a boolean option off.
+unsafe fn set_option_with(handler: *mut Struct_archive, setter: OptionSetter, module: Option<&str>, key: &str, value: Option<&str>) -> Result<(), ArchiveError> {
+    let c_module = match module {
+        Some(m) => Some(try!(to_cstring(m))),
+        None => None
+    };
+    let c_key = try!(to_cstring(key));
+    let c_value = match value {
+        Some(v) => Some(try!(to_cstring(v))),
+        None => None
+    };
+    let module_ptr = match c_module {
+        Some(ref m) => m.as_ptr(),
+        None => ptr::null()
+    };
+    let value_ptr = match c_value {
+        Some(ref v) => v.as_ptr(),
+        None => ptr::null()
+    };
+    let res = setter(handler, module_ptr, c_key.as_ptr(), value_ptr);
+    check_option(handler, res)
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if self.message.is_empty() {
+            write!(fmt, "libarchive error {:?} (errno {})", self.code, self.errno)
+        } else {
+            write!(fmt, "{} ({:?}, errno {})", self.message, self.code, self.errno)
+        }
+    }
+}
+
+impl Error for ArchiveError {
+    fn description(&self) -> &str {
+        if self.message.is_empty() {
+            "libarchive error"
+        } else {
+            &self.message
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        self.cause.as_ref().map(|err| &**err as &Error)
+    }
+}
+
+impl From<ArchiveError> for std::io::Error {
+    fn from(err: ArchiveError) -> std::io::Error {
+        let kind = if let Some(ref cause) = err.cause {
+            cause.kind()
+        } else if err.errno > 0 {
+            std::io::Error::from_raw_os_error(err.errno).kind()
+        } else {
+            std::io::ErrorKind::Other
+        };
+        // `Read` callers retry on `Interrupted`, which would not get past a libarchive error
+        let kind = if kind == std::io::ErrorKind::Interrupted { std::io::ErrorKind::Other } else { kind };
+        std::io::Error::new(kind, err)
     }
 }
 
@@ -159,15 +559,40 @@ fn flags_to_code(flags : Vec<ArchiveExtractFlag>) -> c_int {
     flags_code
 }
 
+trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek> ReadSeek for T {}
+
+enum StreamSource {
+    Plain(Box<Read>),
+    Seekable(Box<ReadSeek>)
+}
+
 struct ReadContainer {
-    reader: Box<Read>,
-    buffer: Vec<u8>,
-    seeker: Option<Box<Seek>>
+    source: StreamSource,
+    buffer: Vec<u8>
 }
 
 impl ReadContainer {
+    fn new(source: StreamSource) -> ReadContainer {
+        let mut buffer = Vec::with_capacity(8192);
+        for _ in 0..8192 {
+            buffer.push(0);
+        }
+        ReadContainer { source: source, buffer: buffer }
+    }
+
     fn read_bytes(&mut self) -> std::io::Result<usize> {
-        self.reader.read(&mut self.buffer[..])
+        match self.source {
+            StreamSource::Plain(ref mut reader) => reader.read(&mut self.buffer[..]),
+            StreamSource::Seekable(ref mut reader) => reader.read(&mut self.buffer[..])
+        }
+    }
+
+    fn seeker(&mut self) -> Option<&mut ReadSeek> {
+        match self.source {
+            StreamSource::Plain(_) => None,
+            StreamSource::Seekable(ref mut reader) => Some(&mut **reader)
+        }
     }
 }
 
@@ -202,31 +627,73 @@ extern "C" fn arch_skip(_: *mut Struct_archive, _client_data: *mut c_void, reque
         let mut rc = Box::from_raw(_client_data as *mut ReadContainer);
 
         // we can't return error code here, but if we return 0 normal read will be called, where error code will be set
-        if rc.seeker.is_none() {
-            Box::into_raw(rc);
-            return 0;
-        }
-        let size = rc.seeker.as_mut().unwrap().seek(std::io::SeekFrom::Current(request)).unwrap_or(0);
+        let skipped = match rc.seeker() {
+            Some(seeker) => match seeker.seek(std::io::SeekFrom::Current(request)) {
+                Ok(_) => request,
+                Err(_) => 0
+            },
+            None => 0
+        };
+
+        Box::into_raw(rc);
+        return skipped;
+    }
+}
+
+extern "C" fn arch_seek(arch: *mut Struct_archive, _client_data: *mut c_void, offset: int64_t, whence: c_int) -> int64_t {
+    unsafe {
+        let mut rc = Box::from_raw(_client_data as *mut ReadContainer);
 
+        let pos = match whence {
+            SEEK_SET => std::io::SeekFrom::Start(offset as u64),
+            SEEK_CUR => std::io::SeekFrom::Current(offset),
+            _ => std::io::SeekFrom::End(offset)
+        };
+        let res = match rc.seeker() {
+            Some(seeker) => seeker.seek(pos),
+            None => Err(std::io::Error::new(std::io::ErrorKind::Other, "stream is not seekable"))
+        };
         Box::into_raw(rc);
-        return size as int64_t;
+
+        match res {
+            Ok(position) => position as int64_t,
+            Err(err) => {
+                let descr = CString::new(err.description()).unwrap();
+                archive_set_error(arch, err.raw_os_error().unwrap_or(0), descr.as_ptr());
+                ARCHIVE_FATAL as int64_t
+            }
+        }
     }
 }
 
 impl Reader {
-    pub fn new() -> Result<Reader, AllocationError> {
+    pub fn new() -> Result<Reader, ArchiveError> {
         unsafe {
             let h = archive_read_new();
 
             if h.is_null() {
-                Err(AllocationError)
+                Err(ArchiveError::allocation())
             } else {
-                Ok(Reader { handler: Rc::new(h) })
+                Ok(Reader { handler: Rc::new(h), policy: WarningPolicy::Collect, warnings: Vec::new() })
 
             }
         }
     }
 
+    pub fn warning_policy(mut self, policy: WarningPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Warnings collected while configuring and opening the archive, and by `read_data`.
+    pub fn warnings(&self) -> &[ArchiveError] {
+        &self.warnings
+    }
+
+    fn check(&self, res: c_int) -> Result<Option<ArchiveError>, ArchiveError> {
+        unsafe { check_result(*self.handler, res, self.policy) }
+    }
+
     pub fn support_filter_all(self) -> Self {
         unsafe {
             archive_read_support_filter_all(*self.handler);
@@ -247,82 +714,304 @@ impl Reader {
         self
     }
 
+    /// Enables a single decompression filter.
+    ///
+    /// libarchive warns when it has to fall back to an external program for the
+    /// filter; such warnings follow the warning policy.
+    pub fn support_filter(mut self, filter: ReadFilter) -> Result<Self, ArchiveError> {
+        unsafe {
+            let res = match filter {
+                ReadFilter::Bzip2 => archive_read_support_filter_bzip2(*self.handler),
+                ReadFilter::Compress => archive_read_support_filter_compress(*self.handler),
+                ReadFilter::Grzip => archive_read_support_filter_grzip(*self.handler),
+                ReadFilter::Gzip => archive_read_support_filter_gzip(*self.handler),
+                ReadFilter::Lrzip => archive_read_support_filter_lrzip(*self.handler),
+                ReadFilter::Lzip => archive_read_support_filter_lzip(*self.handler),
+                ReadFilter::Lzma => archive_read_support_filter_lzma(*self.handler),
+                ReadFilter::Lzop => archive_read_support_filter_lzop(*self.handler),
+                ReadFilter::None => archive_read_support_filter_none(*self.handler),
+                ReadFilter::Program { ref command, ref signature } => {
+                    let c_command = try!(to_cstring(command));
+                    match *signature {
+                        Some(ref signature) => archive_read_support_filter_program_signature(
+                            *self.handler, c_command.as_ptr(),
+                            signature.as_ptr() as *const c_void, signature.len() as size_t),
+                        None => archive_read_support_filter_program(*self.handler, c_command.as_ptr())
+                    }
+                },
+                ReadFilter::Rpm => archive_read_support_filter_rpm(*self.handler),
+                ReadFilter::Uu => archive_read_support_filter_uu(*self.handler),
+                ReadFilter::Xz => archive_read_support_filter_xz(*self.handler)
+            };
+            if let Some(warning) = try!(self.check(res)) {
+                self.warnings.push(warning);
+            }
+        }
+        Ok(self)
+    }
+
+    /// Decodes the input with an external program before any other filter,
+    /// without relying on detection.
+    pub fn append_filter_program(mut self, command: &str, signature: Option<&[u8]>) -> Result<Self, ArchiveError> {
+        let c_command = try!(to_cstring(command));
+        unsafe {
+            let res = match signature {
+                Some(signature) => archive_read_append_filter_program_signature(
+                    *self.handler, c_command.as_ptr(),
+                    signature.as_ptr() as *const c_void, signature.len() as size_t),
+                None => archive_read_append_filter_program(*self.handler, c_command.as_ptr())
+            };
+            if let Some(warning) = try!(self.check(res)) {
+                self.warnings.push(warning);
+            }
+        }
+        Ok(self)
+    }
+
+    /// Enables a single format, so that uploads in any other format are rejected
+    /// instead of being handed to a parser we did not ask for.
+    ///
+    /// Variants of one family share a reader: enabling `Ustar` accepts any tar
+    /// archive. Fails for formats libarchive cannot read, see `ArchiveFormat::can_read`.
+    pub fn support_format(mut self, format: ArchiveFormat) -> Result<Self, ArchiveError> {
+        unsafe {
+            let res = match format {
+                ArchiveFormat::_7Zip => archive_read_support_format_7zip(*self.handler),
+                ArchiveFormat::Ar_Bsd | ArchiveFormat::Ar_Svr4 =>
+                    archive_read_support_format_ar(*self.handler),
+                ArchiveFormat::Cab => archive_read_support_format_cab(*self.handler),
+                ArchiveFormat::Cpio | ArchiveFormat::Cpio_newc =>
+                    archive_read_support_format_cpio(*self.handler),
+                ArchiveFormat::Empty => archive_read_support_format_empty(*self.handler),
+                ArchiveFormat::Gnutar => archive_read_support_format_gnutar(*self.handler),
+                ArchiveFormat::Iso9660 => archive_read_support_format_iso9660(*self.handler),
+                ArchiveFormat::Lha => archive_read_support_format_lha(*self.handler),
+                ArchiveFormat::Mtree | ArchiveFormat::Mtree_Classic =>
+                    archive_read_support_format_mtree(*self.handler),
+                ArchiveFormat::Rar => archive_read_support_format_rar(*self.handler),
+                ArchiveFormat::Raw => archive_read_support_format_raw(*self.handler),
+                ArchiveFormat::Pax | ArchiveFormat::Pax_Restricted | ArchiveFormat::Tar |
+                ArchiveFormat::Ustar | ArchiveFormat::V7tar =>
+                    archive_read_support_format_tar(*self.handler),
+                ArchiveFormat::Warc => archive_read_support_format_warc(*self.handler),
+                ArchiveFormat::Xar => archive_read_support_format_xar(*self.handler),
+                ArchiveFormat::Zip => archive_read_support_format_zip(*self.handler),
+                ArchiveFormat::Shar | ArchiveFormat::Shar_Dump =>
+                    return Err(ArchiveError::custom("libarchive cannot read shar archives"))
+            };
+            if let Some(warning) = try!(self.check(res)) {
+                self.warnings.push(warning);
+            }
+        }
+        Ok(self)
+    }
+
+    /// Reads the archive as `format` instead of auto-detecting it, e.g. to read the
+    /// tar stored inside an ISO image or a raw stream that looks like mtree.
+    ///
+    /// Only the base format matters: `Pax`, `Ustar` or `Gnutar` all force tar.
+    pub fn set_format(mut self, format: ArchiveFormat) -> Result<Self, ArchiveError> {
+        unsafe {
+            let res = archive_read_support_format_by_code(*self.handler, format.code());
+            if let Some(warning) = try!(self.check(res)) {
+                self.warnings.push(warning);
+            }
+            let res = archive_read_set_format(*self.handler, format.code());
+            if let Some(warning) = try!(self.check(res)) {
+                self.warnings.push(warning);
+            }
+        }
+        Ok(self)
+    }
+
+    /// Charset the entry names are stored in, e.g. `"CP437"` for old Windows zip files.
+    ///
+    /// Applies to every enabled format that knows the `hdrcharset` option, so it
+    /// must come after the `support_format_*` calls. Fails if no enabled format
+    /// supports it or libarchive cannot convert from that charset.
+    pub fn set_header_charset(self, charset: &str) -> Result<Self, ArchiveError> {
+        self.set_format_option(None, "hdrcharset", charset)
+    }
+
+    /// Sets a format or filter option, e.g. `set_option(Some("zip"), "compat-2x", "1")`.
+    ///
+    /// Options apply to the modules enabled so far; with no module given, to every
+    /// module knowing the key. Unknown keys and rejected values are errors.
+    pub fn set_option(self, module: Option<&str>, key: &str, value: &str) -> Result<Self, ArchiveError> {
+        unsafe { try!(set_option_with(*self.handler, archive_read_set_option, module, key, Some(value))); }
+        Ok(self)
+    }
+
+    /// Same as `set_option`, restricted to format modules.
+    pub fn set_format_option(self, module: Option<&str>, key: &str, value: &str) -> Result<Self, ArchiveError> {
+        unsafe { try!(set_option_with(*self.handler, archive_read_set_format_option, module, key, Some(value))); }
+        Ok(self)
+    }
+
+    /// Same as `set_option`, restricted to filter modules.
+    pub fn set_filter_option(self, module: Option<&str>, key: &str, value: &str) -> Result<Self, ArchiveError> {
+        unsafe { try!(set_option_with(*self.handler, archive_read_set_filter_option, module, key, Some(value))); }
+        Ok(self)
+    }
+
+    /// Sets options from a comma separated string as accepted by bsdtar's
+    /// `--options`, e.g. `"zip:hdrcharset=CP437,!read_concatenated_archives"`.
+    pub fn set_options(self, options: &str) -> Result<Self, ArchiveError> {
+        let c_options = try!(to_cstring(options));
+        unsafe {
+            let res = archive_read_set_options(*self.handler, c_options.as_ptr());
+            try!(check_option(*self.handler, res));
+        }
+        Ok(self)
+    }
+
 
-    pub fn open_filename(self, fileName: &str, bufferSize: u64 ) -> Result<Self, ArchiveError> {
+    pub fn open_filename(mut self, fileName: &str, bufferSize: u64 ) -> Result<Self, ArchiveError> {
         let fname = CString::new(fileName).unwrap();
         unsafe {
             let res = archive_read_open_filename(*self.handler, fname.as_ptr(), bufferSize);
-            if res==ARCHIVE_OK {
-                Ok(self)
-            } else {
-                Err(code_to_error(res))
+            if let Some(warning) = try!(self.check(res)) {
+                self.warnings.push(warning);
             }
+            Ok(self)
         }
     }
 
-    pub fn open_memory(self, memory: &mut [u8]) -> Result<Self, ArchiveError> {
+    pub fn open_memory(mut self, memory: &mut [u8]) -> Result<Self, ArchiveError> {
         unsafe {
             let memptr: *mut u8 = &mut memory[0];
             let res = archive_read_open_memory(*self.handler, memptr as *mut c_void, memory.len() as u64);
-            if res==ARCHIVE_OK {
-                Ok(self)
-            } else {
-                Err(code_to_error(res))
+            if let Some(warning) = try!(self.check(res)) {
+                self.warnings.push(warning);
             }
+            Ok(self)
         }
     }
 
     pub fn open_stream<T: Any+Read>(self, source: T) -> Result<Self, ArchiveError> {
+        self.open_container(StreamSource::Plain(Box::new(source)))
+    }
+
+    /// Opens a seekable source, letting libarchive skip over entry data and jump
+    /// to central directories (zip, 7z) instead of reading the whole stream.
+    pub fn open_seekable_stream<T: Any+Read+Seek>(self, source: T) -> Result<Self, ArchiveError> {
+        self.open_container(StreamSource::Seekable(Box::new(source)))
+    }
+
+    fn open_container(mut self, source: StreamSource) -> Result<Self, ArchiveError> {
         unsafe {
-            let mut rc_unboxed =  ReadContainer { reader: Box::new(source), buffer: Vec::with_capacity(8192), seeker: None};
-            for _ in 0..8192 {
-                rc_unboxed.buffer.push(0);
+            let seekable = match source {
+                StreamSource::Seekable(_) => true,
+                StreamSource::Plain(_) => false
+            };
+            let rc = Box::new(ReadContainer::new(source));
+
+            archive_read_set_read_callback(*self.handler, arch_read);
+            archive_read_set_skip_callback(*self.handler, arch_skip);
+            if seekable {
+                archive_read_set_seek_callback(*self.handler, arch_seek);
             }
-            let rc = Box::new( rc_unboxed );
+            archive_read_set_close_callback(*self.handler, arch_close);
+            archive_read_set_callback_data(*self.handler, Box::into_raw(rc) as *mut c_void);
 
-            let res = archive_read_open(
-                        *self.handler,
-                        Box::into_raw(rc) as *mut c_void,
-                        ptr::null_mut(),
-                        arch_read,
-                        arch_close);
-            if res==ARCHIVE_OK {
-                Ok(self)
-            } else {
-                Err(code_to_error(res))
+            let res = archive_read_open1(*self.handler);
+            if let Some(warning) = try!(self.check(res)) {
+                self.warnings.push(warning);
             }
+            Ok(self)
         }
     }
 
-    pub fn next_header<'s>(&'s self) -> Result<ArchiveEntryReader, ArchiveError> {
+    /// Reads the next header. The entry borrows the reader, which cannot move to
+    /// another entry until the current one is dropped.
+    pub fn next_header<'s>(&'s mut self) -> Result<ArchiveEntryReader<'s>, ArchiveError> {
         unsafe {
             let mut entry: *mut Struct_archive_entry = ptr::null_mut();
             let res = archive_read_next_header(*self.handler, &mut entry);
-            if res==ARCHIVE_OK {
-                Ok( ArchiveEntryReader { entry: entry, handler: self.handler.clone() } )
-            } else {
-                Err(code_to_error(res))
+            let warning = try!(self.check(res));
+            Ok( ArchiveEntryReader {
+                entry: entry,
+                reader: self,
+                warnings: warning.into_iter().collect()
+            } )
+        }
+    }
+
+    /// Closes the archive, reporting errors that dropping the reader would swallow.
+    ///
+    /// Returns the warnings collected while configuring, opening and reading with `read_data`.
+    pub fn close(mut self) -> Result<Vec<ArchiveError>, ArchiveError> {
+        unsafe {
+            let res = archive_read_close(*self.handler);
+            if let Some(warning) = try!(self.check(res)) {
+                self.warnings.push(warning);
             }
         }
+        Ok(std::mem::replace(&mut self.warnings, Vec::new()))
     }
 
-    pub fn read_data<'s>(&'s self, size : size_t) -> Result<Vec<u8>, ArchiveError> {
+    /// Iterates over the remaining entries of the archive.
+    ///
+    /// Each entry borrows the iterator, so its data has to be read before asking
+    /// for the next one; advancing skips whatever is left of the current entry.
+    pub fn entries<'s>(&'s mut self) -> Entries<'s> {
+        Entries { reader: self, done: false }
+    }
+
+    /// Reads up to `size` bytes of the current entry data.
+    ///
+    /// Returns an empty vector once the entry data is exhausted. Warnings allowed by
+    /// the warning policy are added to `warnings`. `ARCHIVE_RETRY` is returned as an
+    /// error: libarchive reports it for damaged data it cannot get past.
+    pub fn read_data<'s>(&'s mut self, size : size_t) -> Result<Vec<u8>, ArchiveError> {
         unsafe {
-          let mut chunk_vec = Vec::with_capacity(size as usize);
+          let mut chunk_vec: Vec<u8> = Vec::with_capacity(size as usize);
           let chunk_ptr = chunk_vec.as_mut_ptr();
-          let res = archive_read_data(*self.handler, chunk_ptr as *mut c_void, size) as i32;
-          if (res==ARCHIVE_FATAL) || (res==ARCHIVE_WARN) || (res==ARCHIVE_RETRY) {
-            Err(code_to_error(res))
-          } else if res==0 {
-            Err(code_to_error(ARCHIVE_EOF))
-          } else {
-            chunk_vec.set_len(size as usize);
-            Ok(chunk_vec)
+          loop {
+            let res = archive_read_data(*self.handler, chunk_ptr as *mut c_void, size);
+            if res >= 0 {
+              chunk_vec.set_len(res as usize);
+              return Ok(chunk_vec);
+            }
+            if let Some(warning) = try!(self.check(res as c_int)) {
+              self.warnings.push(warning);
+            }
           }
         }
     }
 }
 
+/// Lending iterator over archive entries, see `Reader::entries`.
+///
+/// Each entry borrows the iterator, so it is consumed with
+/// `while let Some(entry) = entries.next_entry() { ... }`.
+pub struct Entries<'a> {
+    reader: &'a mut Reader,
+    done: bool
+}
+
+impl<'a> Entries<'a> {
+    /// Ends on `ARCHIVE_EOF`; other failures are yielded as `Err`, and iteration
+    /// stops after any of them but `ARCHIVE_WARN`, `ARCHIVE_RETRY` and `ARCHIVE_FAILED`,
+    /// after which libarchive can go on with the next header.
+    pub fn next_entry<'s>(&'s mut self) -> Option<Result<ArchiveEntryReader<'s>, ArchiveError>> {
+        if self.done {
+            return None;
+        }
+        match self.reader.next_header() {
+            Ok(entry) => Some(Ok(entry)),
+            Err(err) => {
+                match err.code() {
+                    ArchiveErrorCode::Eof => { self.done = true; None }
+                    ArchiveErrorCode::Warn | ArchiveErrorCode::Retry |
+                    ArchiveErrorCode::Failed => Some(Err(err)),
+                    _ => { self.done = true; Some(Err(err)) }
+                }
+            }
+        }
+    }
+}
+
 impl Drop for Reader {
 	fn drop(&mut self) {
 		if Rc::is_unique(&self.handler) {
@@ -331,160 +1020,593 @@ impl Drop for Reader {
 	}
 }
 
-#[allow(raw_pointer_derive)]
-#[derive(PartialEq, Clone)]
-pub struct Writer {
+/// Archive writer. `'buf` is the lifetime of the buffer given to `open_memory`,
+/// libarchive writes into it until the writer is dropped.
+#[derive(Clone)]
+pub struct Writer<'buf> {
 	handler: Rc<*mut Struct_archive>,
-  outUsed : Rc<*mut size_t>
+  memory: PhantomData<&'buf mut [u8]>,
+  outUsed : Rc<UnsafeCell<size_t>>,
+  output: Option<Rc<RefCell<Vec<u8>>>>,
+  policy: WarningPolicy,
+  warnings: Vec<ArchiveError>,
+  sink: Rc<RefCell<Option<Box<WriteContainer>>>>,
+  sink_error: Rc<RefCell<Option<std::io::Error>>>
+}
+
+impl<'buf> PartialEq for Writer<'buf> {
+  fn eq(&self, other: &Writer<'buf>) -> bool {
+    self.handler == other.handler
+  }
 }
 
-impl Drop for Writer {
+impl<'buf> Drop for Writer<'buf> {
 	fn drop(&mut self) {
 		if Rc::is_unique(&self.handler) {
 			unsafe { 
         archive_write_free(*self.handler); 
       }
 		}
+		// the sink, if any, is dropped with the fields, once libarchive is done with it
 	}
 }
 
-impl Writer {
-	pub fn new() -> Result<Writer, AllocationError> {
+/// Writes data of the current entry, see `Writer::write_data`.
+impl<'buf> Write for Writer<'buf> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        Ok(try!(self.write_data(buf)))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+struct WriteContainer {
+    writer: Box<Write>,
+    error: Rc<RefCell<Option<std::io::Error>>>
+}
+
+impl WriteContainer {
+    unsafe fn fail(&self, arch: *mut Struct_archive, err: std::io::Error) {
+        let descr = CString::new(err.description()).unwrap();
+        archive_set_error(arch, err.raw_os_error().unwrap_or(0), descr.as_ptr());
+        *self.error.borrow_mut() = Some(err);
+    }
+}
+
+/// Sink for `Writer::open_vec`, shared with the writer so the data survives the close callback.
+struct SharedVec(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedVec {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend(buf.iter().cloned());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+extern "C" fn arch_write(arch: *mut Struct_archive, _client_data: *mut c_void, _buffer: *const c_void, _length: size_t) -> ssize_t {
+    unsafe {
+        // client_data points to the WriteContainer owned by the Writer
+        let wc = &mut *(_client_data as *mut WriteContainer);
+        let data = std::slice::from_raw_parts(_buffer as *const u8, _length as usize);
+        match wc.writer.write_all(data) {
+            Ok(()) => _length as ssize_t,
+            Err(err) => { wc.fail(arch, err); -1 }
+        }
+    }
+}
+
+/// Only flushes: libarchive skips the close callback after a fatal error, so the
+/// container is freed by the `Writer` instead.
+extern "C" fn arch_write_close(arch: *mut Struct_archive, _client_data: *mut c_void) -> c_int {
+    unsafe {
+        let wc = &mut *(_client_data as *mut WriteContainer);
+        match wc.writer.flush() {
+            Ok(()) => ARCHIVE_OK,
+            Err(err) => { wc.fail(arch, err); ARCHIVE_FATAL }
+        }
+    }
+}
+
+impl<'buf> Writer<'buf> {
+	pub fn new() -> Result<Writer<'buf>, ArchiveError> {
 		unsafe {
 			let h = archive_write_new();
 			if h.is_null() {
-				Err(AllocationError)
+				Err(ArchiveError::allocation())
 			} else {
-        let mut init_used: Box<size_t> = Box::new(0);
-        let outUsed: *mut size_t = &mut *init_used;
-				Ok(Writer { handler: Rc::new(h), outUsed: Rc::new(outUsed)})
+				Ok(Writer {
+          handler: Rc::new(h),
+          memory: PhantomData,
+          outUsed: Rc::new(UnsafeCell::new(0)),
+          output: None,
+          policy: WarningPolicy::Collect,
+          warnings: Vec::new(),
+          sink: Rc::new(RefCell::new(None)),
+          sink_error: Rc::new(RefCell::new(None))
+        })
 			}
 		}
 	}
-  pub fn add_filter(self, filter : ArchiveFilter) -> Self {
-    unsafe {
-      match filter {
-        ArchiveFilter::Bzip2 => archive_write_add_filter_bzip2(*self.handler),
-        ArchiveFilter::Compress => archive_write_add_filter_compress(*self.handler),
-        ArchiveFilter::Gzip => archive_write_add_filter_gzip(*self.handler),
-        ArchiveFilter::Lzip => archive_write_add_filter_lzip(*self.handler),
-        ArchiveFilter::Lzma => archive_write_add_filter_lzma(*self.handler),
-        ArchiveFilter::None => archive_write_add_filter_none(*self.handler),
-        // TODO : Program(&str)
-        ArchiveFilter::Xz => archive_write_add_filter_xz(*self.handler)
-      };
-    }
+
+  pub fn warning_policy(mut self, policy: WarningPolicy) -> Self {
+    self.policy = policy;
     self
   }
 
-  pub fn set_format(self, format : ArchiveFormat) -> Self {
-    unsafe {
-      match format {
-        ArchiveFormat::_7Zip => archive_write_set_format_7zip(*self.handler),
-        ArchiveFormat::Ar_Bsd => archive_write_set_format_ar_bsd(*self.handler),
-        ArchiveFormat::Ar_Svr4 => archive_write_set_format_ar_svr4(*self.handler),
-        ArchiveFormat::Cpio => archive_write_set_format_cpio(*self.handler),
-        ArchiveFormat::Cpio_newc => archive_write_set_format_cpio_newc(*self.handler),
-        ArchiveFormat::Gnutar => archive_write_set_format_gnutar(*self.handler),
-        ArchiveFormat::Iso9600 => archive_write_set_format_iso9660(*self.handler),
-        ArchiveFormat::Mtree => archive_write_set_format_mtree(*self.handler),
-        // ArchiveFormat::Mtree_Classic => archive_write_set_format_mtree_classic(*self.handler),
-        ArchiveFormat::Pax => archive_write_set_format_pax(*self.handler),
-        ArchiveFormat::Pax_Restricted => archive_write_set_format_pax_restricted(*self.handler),
-        ArchiveFormat::Shar => archive_write_set_format_shar(*self.handler),
+  /// Warnings collected since the writer was created or since the last `take_warnings`.
+  pub fn warnings(&self) -> &[ArchiveError] {
+    &self.warnings
+  }
+
+  pub fn take_warnings(&mut self) -> Vec<ArchiveError> {
+    std::mem::replace(&mut self.warnings, Vec::new())
+  }
+
+  fn check(&mut self, res: c_int) -> Result<&mut Self, ArchiveError> {
+    match unsafe { check_result(*self.handler, res, self.policy) } {
+      Ok(Some(warning)) => self.warnings.push(warning),
+      Ok(None) => {},
+      Err(err) => return Err(self.attach_sink_error(err))
+    }
+    Ok(self)
+  }
+
+  /// Attaches the I/O error recorded by the stream callbacks, if any, to `err`.
+  fn attach_sink_error(&self, err: ArchiveError) -> ArchiveError {
+    match self.sink_error.borrow_mut().take() {
+      Some(cause) => err.with_cause(cause),
+      None => err
+    }
+  }
+
+  /// Charset to store entry names in, e.g. `"UTF-8"` or `"CP437"`.
+  ///
+  /// Applies to the format set with `set_format`, so it must come after it. Fails
+  /// if the format does not support it or libarchive cannot convert to that charset.
+  pub fn set_header_charset(self, charset: &str) -> Result<Self, ArchiveError> {
+    self.set_format_option(None, "hdrcharset", charset)
+  }
+
+  /// Sets a format or filter option, e.g. `set_option(Some("gzip"), "compression-level", "9")`.
+  ///
+  /// Options apply to the format and filters set so far; with no module given, to
+  /// every module knowing the key. Unknown keys and rejected values are errors.
+  pub fn set_option(self, module: Option<&str>, key: &str, value: &str) -> Result<Self, ArchiveError> {
+    unsafe { try!(set_option_with(*self.handler, archive_write_set_option, module, key, Some(value))); }
+    Ok(self)
+  }
+
+  /// Same as `set_option`, restricted to the format module.
+  pub fn set_format_option(self, module: Option<&str>, key: &str, value: &str) -> Result<Self, ArchiveError> {
+    unsafe { try!(set_option_with(*self.handler, archive_write_set_format_option, module, key, Some(value))); }
+    Ok(self)
+  }
+
+  /// Same as `set_option`, restricted to filter modules.
+  pub fn set_filter_option(self, module: Option<&str>, key: &str, value: &str) -> Result<Self, ArchiveError> {
+    unsafe { try!(set_option_with(*self.handler, archive_write_set_filter_option, module, key, Some(value))); }
+    Ok(self)
+  }
+
+  /// Sets options from a comma separated string as accepted by bsdtar's
+  /// `--options`, e.g. `"gzip:compression-level=9,iso9660:volume-id=DATA"`.
+  pub fn set_options(self, options: &str) -> Result<Self, ArchiveError> {
+    let c_options = try!(to_cstring(options));
+    unsafe {
+      let res = archive_write_set_options(*self.handler, c_options.as_ptr());
+      try!(check_option(*self.handler, res));
+    }
+    Ok(self)
+  }
+
+  /// Appends `filter` to the filter chain.
+  ///
+  /// Filters are applied in the order they are added: the first one receives
+  /// the archive data, the last one writes to the output. Adding `Xz` then
+  /// `B64encode` produces a base64 encoded `.xz` archive.
+  ///
+  /// Fails if the linked libarchive cannot provide the filter, e.g. `Lzip`
+  /// without liblzma. Filters falling back to an external program warn instead.
+  pub fn add_filter(mut self, filter : ArchiveFilter) -> Result<Self, ArchiveError> {
+    unsafe {
+      let res = match filter {
+        ArchiveFilter::B64encode => archive_write_add_filter_b64encode(*self.handler),
+        ArchiveFilter::Bzip2 => archive_write_add_filter_bzip2(*self.handler),
+        ArchiveFilter::Compress => archive_write_add_filter_compress(*self.handler),
+        ArchiveFilter::Grzip => archive_write_add_filter_grzip(*self.handler),
+        ArchiveFilter::Gzip => archive_write_add_filter_gzip(*self.handler),
+        ArchiveFilter::Lrzip => archive_write_add_filter_lrzip(*self.handler),
+        ArchiveFilter::Lzip => archive_write_add_filter_lzip(*self.handler),
+        ArchiveFilter::Lzma => archive_write_add_filter_lzma(*self.handler),
+        ArchiveFilter::Lzop => archive_write_add_filter_lzop(*self.handler),
+        ArchiveFilter::None => archive_write_add_filter_none(*self.handler),
+        ArchiveFilter::Program { ref command } => {
+          let c_command = try!(to_cstring(command));
+          archive_write_add_filter_program(*self.handler, c_command.as_ptr())
+        },
+        ArchiveFilter::Uuencode => archive_write_add_filter_uuencode(*self.handler),
+        ArchiveFilter::Xz => archive_write_add_filter_xz(*self.handler),
+        ArchiveFilter::__Nonexhaustive => return Err(ArchiveError::custom("unsupported filter"))
+      };
+      try!(self.check(res));
+    }
+    Ok(self)
+  }
+
+  /// Appends `filters` to the filter chain, in order, see `add_filter`.
+  pub fn add_filters(self, filters : Vec<ArchiveFilter>) -> Result<Self, ArchiveError> {
+    let mut writer = self;
+    for filter in filters {
+      writer = try!(writer.add_filter(filter));
+    }
+    Ok(writer)
+  }
+
+  /// Appends the filter libarchive knows as `name`, e.g. `"zstd"`, to the
+  /// filter chain.
+  pub fn add_filter_by_name(mut self, name : &str) -> Result<Self, ArchiveError> {
+    let c_name = try!(to_cstring(name));
+    unsafe {
+      let res = archive_write_add_filter_by_name(*self.handler, c_name.as_ptr());
+      try!(self.check(res));
+    }
+    Ok(self)
+  }
+
+  /// Appends the filter with the given `ARCHIVE_FILTER_*` code to the filter
+  /// chain.
+  pub fn add_filter_by_code(mut self, code : i32) -> Result<Self, ArchiveError> {
+    unsafe {
+      let res = archive_write_add_filter(*self.handler, code);
+      try!(self.check(res));
+    }
+    Ok(self)
+  }
+
+  /// Adds a filter tuned with `options`, e.g.
+  /// `add_filter_with(ArchiveFilter::Gzip, &[FilterOption::CompressionLevel(9), FilterOption::Timestamp(false)])`.
+  ///
+  /// Fails if the filter does not accept one of the options.
+  pub fn add_filter_with(self, filter : ArchiveFilter, options : &[FilterOption]) -> Result<Self, ArchiveError> {
+    let module = filter.module_name();
+    let writer = try!(self.add_filter(filter));
+    for option in options {
+      let (key, value) = option.key_value();
+      unsafe {
+        try!(set_option_with(*writer.handler, archive_write_set_filter_option, Some(module), key, value.as_ref().map(|v| &v[..])));
+      }
+    }
+    Ok(writer)
+  }
+
+  /// Fails for formats libarchive can only read, see `ArchiveFormat::can_write`.
+  pub fn set_format(mut self, format : ArchiveFormat) -> Result<Self, ArchiveError> {
+    unsafe {
+      let res = match format {
+        ArchiveFormat::_7Zip => archive_write_set_format_7zip(*self.handler),
+        ArchiveFormat::Ar_Bsd => archive_write_set_format_ar_bsd(*self.handler),
+        ArchiveFormat::Ar_Svr4 => archive_write_set_format_ar_svr4(*self.handler),
+        ArchiveFormat::Cpio => archive_write_set_format_cpio(*self.handler),
+        ArchiveFormat::Cpio_newc => archive_write_set_format_cpio_newc(*self.handler),
+        ArchiveFormat::Gnutar => archive_write_set_format_gnutar(*self.handler),
+        ArchiveFormat::Iso9660 => archive_write_set_format_iso9660(*self.handler),
+        ArchiveFormat::Mtree => archive_write_set_format_mtree(*self.handler),
+        ArchiveFormat::Mtree_Classic => archive_write_set_format_mtree_classic(*self.handler),
+        ArchiveFormat::Pax => archive_write_set_format_pax(*self.handler),
+        ArchiveFormat::Pax_Restricted => archive_write_set_format_pax_restricted(*self.handler),
+        ArchiveFormat::Raw => archive_write_set_format_raw(*self.handler),
+        ArchiveFormat::Shar => archive_write_set_format_shar(*self.handler),
         ArchiveFormat::Shar_Dump => archive_write_set_format_shar_dump(*self.handler),
         ArchiveFormat::Ustar => archive_write_set_format_ustar(*self.handler),
-        // ArchiveFormat::V7tar => archive_write_set_format_v7tar(*self.handler),
+        ArchiveFormat::V7tar => archive_write_set_format_v7tar(*self.handler),
+        ArchiveFormat::Warc => archive_write_set_format_warc(*self.handler),
         ArchiveFormat::Xar => archive_write_set_format_xar(*self.handler),
         ArchiveFormat::Zip => archive_write_set_format_zip(*self.handler),
+        // libarchive picks restricted pax for plain tar and rejects read-only formats
+        ArchiveFormat::Cab | ArchiveFormat::Empty | ArchiveFormat::Lha |
+        ArchiveFormat::Rar | ArchiveFormat::Tar =>
+          archive_write_set_format(*self.handler, format.code()),
       };
+      try!(self.check(res));
     }
-    self
+    Ok(self)
   }
 
-  pub fn set_compression(self, filter : ArchiveFilter) -> Self {
+  /// Sets the format libarchive knows as `name`, e.g. `"pax"`, `"v7tar"` or
+  /// `"mtree-classic"`.
+  pub fn set_format_by_name(mut self, name : &str) -> Result<Self, ArchiveError> {
+    let c_name = try!(to_cstring(name));
     unsafe {
-      match filter {
-        ArchiveFilter::Bzip2 => archive_write_set_compression_bzip2(*self.handler),
-        ArchiveFilter::Compress => archive_write_set_compression_compress(*self.handler),
-        ArchiveFilter::Gzip => archive_write_set_compression_gzip(*self.handler),
-        ArchiveFilter::Lzip => archive_write_set_compression_lzip(*self.handler),
-        ArchiveFilter::Lzma => archive_write_set_compression_lzma(*self.handler),
-        ArchiveFilter::None => archive_write_set_compression_none(*self.handler),
-        ArchiveFilter::Xz => archive_write_set_compression_xz(*self.handler)
-      };
+      let res = archive_write_set_format_by_name(*self.handler, c_name.as_ptr());
+      try!(self.check(res));
     }
-    self
+    Ok(self)
+  }
+
+  /// Compatibility wrapper for the deprecated `archive_write_set_compression_*`
+  /// calls, same as `add_filter`.
+  ///
+  /// Unlike those calls it does not drop filters added before, so call it once.
+  #[deprecated(note = "use `add_filter`")]
+  pub fn set_compression(self, filter : ArchiveFilter) -> Result<Self, ArchiveError> {
+    self.add_filter(filter)
   }
 
   pub fn open_filename(&mut self, fileName: &str) -> Result<&mut Self, ArchiveError> {
       let fname = CString::new(fileName).unwrap();
       unsafe {
           let res = archive_write_open_filename(*self.handler, fname.as_ptr());
-          if res==ARCHIVE_OK {
-              Ok(self)
-          } else {
-              Err(code_to_error(res))
-          }
+          self.check(res)
       }
   }
 
-  pub fn open_memory(&mut self, memory: &mut [u8]) -> Result<&mut Self, ArchiveError> {
+  /// Writes the archive into `memory`, failing once it is full; see `close_memory`.
+  pub fn open_memory(&mut self, memory: &'buf mut [u8]) -> Result<&mut Self, ArchiveError> {
+      if memory.is_empty() {
+          return Err(ArchiveError::custom("memory buffer is empty"));
+      }
       unsafe {
-          let memptr: *mut u8 = &mut memory[0];
-          let res = archive_write_open_memory(*self.handler, memptr as *mut c_void, memory.len() as u64, *self.outUsed);
-          if res==ARCHIVE_OK {
-              Ok(self)
-          } else {
-              Err(code_to_error(res))
-          }
+          let memptr: *mut u8 = memory.as_mut_ptr();
+          let res = archive_write_open_memory(*self.handler, memptr as *mut c_void, memory.len() as u64, self.outUsed.get());
+          self.check(res)
       }
   }
 
-  pub fn write_header(&mut self, entry: ArchiveEntryReader) -> Result<&mut Self, ArchiveError> {
+  /// Writes the archive to an arbitrary sink.
+  ///
+  /// If the sink fails, the returned `ArchiveError` keeps the original error,
+  /// see `ArchiveError::io_error`.
+  pub fn open_stream<W: Any+Write>(&mut self, sink: W) -> Result<&mut Self, ArchiveError> {
+      if self.sink.borrow().is_some() {
+          // libarchive may still write to the current sink
+          return Err(ArchiveError::custom("writer is already open"));
+      }
+      let mut wc = Box::new(WriteContainer { writer: Box::new(sink), error: self.sink_error.clone() });
+      let client_data = &mut *wc as *mut WriteContainer as *mut c_void;
+      *self.sink.borrow_mut() = Some(wc);
       unsafe {
-        let res = archive_write_header(*self.handler, entry.entry);
-        if res==ARCHIVE_OK {
-            Ok(self)
-        } else {
-            Err(code_to_error(res))
-        }
+          let res = archive_write_open(
+                      *self.handler,
+                      client_data,
+                      None,
+                      arch_write,
+                      Some(arch_write_close));
+          self.check(res)
       }
   }
 
-  pub fn write_header_new(&mut self, pathname: &str, entry_size: i64) -> Result<&mut Self, ArchiveError> {
+  /// Flushes the last blocks, compression trailers and format footers (such as
+  /// the zip central directory) and closes the archive.
+  ///
+  /// Dropping the writer does the same but has to swallow errors. Returns the
+  /// warnings collected over the lifetime of the writer.
+  pub fn close(mut self) -> Result<Vec<ArchiveError>, ArchiveError> {
       unsafe {
-        let new_entry = archive_entry_new();
-        archive_entry_set_perm(new_entry, 0o755);
-        archive_entry_set_size(new_entry, entry_size);
-        let entry = ArchiveEntryReader { entry: new_entry, handler: self.handler.clone() };
-        entry.set_filetype(ArchiveEntryFiletype::AE_IFREG);
-        entry.set_pathname(pathname);
-
-        self.write_header(entry)
+          let res = archive_write_close(*self.handler);
+          try!(self.check(res));
       }
+      Ok(self.take_warnings())
+  }
+
+  /// Abandons the archive: nothing more is flushed to the output, neither now
+  /// nor when the writer is dropped.
+  ///
+  /// The sink given to `open_stream` is dropped without being flushed, once the
+  /// last clone of the writer is gone.
+  pub fn fail(self) {
+      unsafe {
+          archive_write_fail(*self.handler);
+      }
+  }
+
+  /// Number of bytes written so far into the buffer given to `open_memory`.
+  ///
+  /// The footer is only written on close, use `close_memory` to get the final size.
+  pub fn memory_used(&self) -> usize {
+      unsafe { *self.outUsed.get() as usize }
   }
 
-  pub fn write_data(&mut self, data: Vec<u8>) -> Result<&mut Self, ArchiveError> {
+  /// Closes an archive opened with `open_memory` and returns the number of bytes
+  /// of the buffer that make up the archive.
+  ///
+  /// Warnings collected by the writer are discarded, inspect `warnings` beforehand if needed.
+  pub fn close_memory(mut self) -> Result<usize, ArchiveError> {
       unsafe {
-        let data_len = data.len();
-        let data_bytes = CString::from_vec_unchecked(data);
-        // TODO: How to handle errors here?
-        archive_write_data(*self.handler, data_bytes.as_ptr() as *mut c_void, data_len as u64);
+          let res = archive_write_close(*self.handler);
+          try!(self.check(res));
       }
+      Ok(self.memory_used())
+  }
+
+  /// Writes the archive into a growable in-memory buffer, retrieved with `into_vec`.
+  pub fn open_vec(&mut self) -> Result<&mut Self, ArchiveError> {
+      let output = Rc::new(RefCell::new(Vec::new()));
+      try!(self.open_stream(SharedVec(output.clone())));
+      self.output = Some(output);
       Ok(self)
   }
-  pub fn write_finish_entry(&mut self) -> Result<&mut Self, ArchiveError> {
+
+  /// Closes an archive opened with `open_vec` and returns its bytes.
+  ///
+  /// Warnings collected by the writer are discarded, inspect `warnings` beforehand if needed.
+  pub fn into_vec(mut self) -> Result<Vec<u8>, ArchiveError> {
+      let output = match self.output.take() {
+          Some(output) => output,
+          None => return Err(ArchiveError::custom("writer was not opened with open_vec"))
+      };
+      try!(self.close());
+      let data = std::mem::replace(&mut *output.borrow_mut(), Vec::new());
+      Ok(data)
+  }
+
+  pub fn write_header<E: AsArchiveEntry>(&mut self, entry: &E) -> Result<&mut Self, ArchiveError> {
       unsafe {
-        let res = archive_write_finish_entry(*self.handler);
-        if res==ARCHIVE_OK {
-            Ok(self)
+        let res = archive_write_header(*self.handler, entry.entry_ptr());
+        self.check(res)
+      }
+  }
+
+  /// Writes the header of a regular file with permissions 0755, see
+  /// `ArchiveEntryBuilder` for any other kind of entry.
+  pub fn write_header_new(&mut self, pathname: &str, entry_size: i64) -> Result<&mut Self, ArchiveError> {
+      let entry = try!(ArchiveEntryBuilder::new())
+        .pathname(pathname)
+        .filetype(FileType::RegularFile)
+        .perm(0o755)
+        .size(entry_size)
+        .build();
+      self.write_header(&entry)
+  }
+
+  /// Writes data of the current entry and returns how much was accepted.
+  ///
+  /// Fewer bytes than given are written once the size declared in the entry
+  /// header is reached.
+  pub fn write_data(&mut self, data: &[u8]) -> Result<usize, ArchiveError> {
+      unsafe {
+        let res = archive_write_data(*self.handler, data.as_ptr() as *const c_void, data.len() as size_t);
+        if res < 0 {
+          try!(self.check(res as c_int));
+          Ok(0)
         } else {
-            Err(code_to_error(res))
+          Ok(res as usize)
         }
       }
   }
+  pub fn write_finish_entry(&mut self) -> Result<&mut Self, ArchiveError> {
+      unsafe {
+        let res = archive_write_finish_entry(*self.handler);
+        self.check(res)
+      }
+  }
+
+}
+
+/// Formats and filters the linked libarchive supports.
+///
+/// libarchive may be built without some libraries. Formats then fail or warn that
+/// they are not supported, and are left out. Filters either fail, or fall back to
+/// an external program that may not be installed; those are listed separately by
+/// `external_read_filters` and `external_write_filters`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capabilities {
+    read_formats: Vec<ArchiveFormat>,
+    read_filters: Vec<ReadFilter>,
+    external_read_filters: Vec<ReadFilter>,
+    write_formats: Vec<ArchiveFormat>,
+    write_filters: Vec<ArchiveFilter>,
+    external_write_filters: Vec<ArchiveFilter>
+}
+
+impl Capabilities {
+    /// Probes every format and filter on throwaway archive handles.
+    pub fn detect() -> Capabilities {
+        let formats = vec![
+            ArchiveFormat::_7Zip, ArchiveFormat::Ar_Bsd, ArchiveFormat::Ar_Svr4,
+            ArchiveFormat::Cab, ArchiveFormat::Cpio, ArchiveFormat::Cpio_newc,
+            ArchiveFormat::Empty, ArchiveFormat::Gnutar, ArchiveFormat::Iso9660,
+            ArchiveFormat::Lha, ArchiveFormat::Mtree, ArchiveFormat::Mtree_Classic,
+            ArchiveFormat::Pax, ArchiveFormat::Pax_Restricted, ArchiveFormat::Rar,
+            ArchiveFormat::Raw, ArchiveFormat::Shar, ArchiveFormat::Shar_Dump,
+            ArchiveFormat::Tar, ArchiveFormat::Ustar, ArchiveFormat::V7tar,
+            ArchiveFormat::Warc, ArchiveFormat::Xar, ArchiveFormat::Zip];
+        let mut capabilities = Capabilities {
+            read_formats: formats.iter().cloned()
+                .filter(|format| format.can_read())
+                .filter(|&format| Reader::new()
+                    .and_then(|r| r.warning_policy(WarningPolicy::Escalate).support_format(format))
+                    .is_ok())
+                .collect(),
+            read_filters: Vec::new(),
+            external_read_filters: Vec::new(),
+            write_formats: formats.iter().cloned()
+                .filter(|format| format.can_write())
+                .filter(|&format| Writer::new()
+                    .and_then(|w| w.warning_policy(WarningPolicy::Escalate).set_format(format))
+                    .is_ok())
+                .collect(),
+            write_filters: Vec::new(),
+            external_write_filters: Vec::new()
+        };
+
+        // filters only warn when falling back to an external program
+        let read_filters = vec![
+            ReadFilter::Bzip2, ReadFilter::Compress, ReadFilter::Grzip, ReadFilter::Gzip,
+            ReadFilter::Lrzip, ReadFilter::Lzip, ReadFilter::Lzma, ReadFilter::Lzop,
+            ReadFilter::None, ReadFilter::Rpm, ReadFilter::Uu, ReadFilter::Xz];
+        for filter in read_filters {
+            match Reader::new().and_then(|r| r.support_filter(filter.clone())) {
+                Ok(ref r) if r.warnings().is_empty() => capabilities.read_filters.push(filter),
+                Ok(_) => capabilities.external_read_filters.push(filter),
+                Err(_) => {}
+            }
+        }
+        let write_filters = vec![
+            ArchiveFilter::B64encode, ArchiveFilter::Bzip2, ArchiveFilter::Compress,
+            ArchiveFilter::Grzip, ArchiveFilter::Gzip, ArchiveFilter::Lrzip,
+            ArchiveFilter::Lzip, ArchiveFilter::Lzma, ArchiveFilter::Lzop,
+            ArchiveFilter::None, ArchiveFilter::Uuencode, ArchiveFilter::Xz];
+        for filter in write_filters {
+            match Writer::new().and_then(|w| w.add_filter(filter.clone())) {
+                Ok(ref w) if w.warnings().is_empty() => capabilities.write_filters.push(filter),
+                Ok(_) => capabilities.external_write_filters.push(filter),
+                Err(_) => {}
+            }
+        }
+        capabilities
+    }
+
+    pub fn read_formats(&self) -> &[ArchiveFormat] {
+        &self.read_formats
+    }
 
+    /// Filters libarchive handles by itself.
+    pub fn read_filters(&self) -> &[ReadFilter] {
+        &self.read_filters
+    }
+
+    /// Filters libarchive can only handle by running an external program.
+    pub fn external_read_filters(&self) -> &[ReadFilter] {
+        &self.external_read_filters
+    }
+
+    pub fn write_formats(&self) -> &[ArchiveFormat] {
+        &self.write_formats
+    }
+
+    /// Filters libarchive handles by itself.
+    pub fn write_filters(&self) -> &[ArchiveFilter] {
+        &self.write_filters
+    }
+
+    /// Filters libarchive can only handle by running an external program.
+    pub fn external_write_filters(&self) -> &[ArchiveFilter] {
+        &self.external_write_filters
+    }
+
+    pub fn can_read_format(&self, format: ArchiveFormat) -> bool {
+        self.read_formats.contains(&format)
+    }
+
+    /// Whether libarchive handles `filter` without an external program.
+    pub fn can_read_filter(&self, filter: &ReadFilter) -> bool {
+        self.read_filters.contains(filter)
+    }
+
+    pub fn can_write_format(&self, format: ArchiveFormat) -> bool {
+        self.write_formats.contains(&format)
+    }
+
+    /// Whether libarchive handles `filter` without an external program.
+    pub fn can_write_filter(&self, filter: &ArchiveFilter) -> bool {
+        self.write_filters.contains(filter)
+    }
 }
 
 #[allow(raw_pointer_derive)]
@@ -494,11 +1616,11 @@ pub struct WriterToDisk {
 }
 
 impl WriterToDisk {
-	pub fn new() -> Result<WriterToDisk, AllocationError> {
+	pub fn new() -> Result<WriterToDisk, ArchiveError> {
 		unsafe {
 			let h = archive_write_disk_new();
 			if h.is_null() {
-					Err(AllocationError)
+					Err(ArchiveError::allocation())
 			} else {
 					Ok(WriterToDisk { handler: Rc::new(h) })
 			}
@@ -514,9 +1636,11 @@ impl Drop for WriterToDisk {
 	}
 }
 
-pub struct ArchiveEntryReader {
+/// Current entry of a `Reader`, see `Reader::next_header`.
+pub struct ArchiveEntryReader<'r> {
     entry: *mut Struct_archive_entry,
-    handler: Rc<*mut Struct_archive>
+    reader: &'r mut Reader,
+    warnings: Vec<ArchiveError>
 }
 
 macro_rules! get_time {
@@ -531,23 +1655,274 @@ macro_rules! get_time {
     };
 }
 
+macro_rules! set_time {
+    ( $fname:ident, $apiname:ident) => {
+        pub fn $fname(&mut self, time: Timespec) {
+            unsafe {
+                (concat_idents!(archive_entry_set_, $apiname))(self.entry, time.sec, time.nsec as c_long);
+            }
+        }
+    };
+}
+
+/// Converts a C string to `String`, replacing invalid UTF-8 and mapping null to "".
 unsafe fn wrap_to_string(ptr: *const c_char) -> String {
-    let path = CStr::from_ptr(ptr);
-    String::from(std::str::from_utf8(path.to_bytes()).unwrap())
+    wrap_to_option_string(ptr).unwrap_or(String::new())
 }
 
-impl ArchiveEntryReader {
-    pub fn size(&self) -> i64 {
-      unsafe {
-        archive_entry_size(self.entry)
-      }
+unsafe fn wrap_to_option_string(ptr: *const c_char) -> Option<String> {
+    wrap_to_bytes(ptr).map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+}
+
+unsafe fn wrap_to_bytes(ptr: *const c_char) -> Option<Vec<u8>> {
+    if ptr.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(ptr).to_bytes().to_vec())
     }
+}
 
-    pub fn pathname(&self) -> String {
-        unsafe {
-            wrap_to_string(archive_entry_pathname(self.entry))
+#[cfg(unix)]
+fn bytes_to_os_string(bytes: Vec<u8>) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+    OsString::from_vec(bytes)
+}
+
+#[cfg(not(unix))]
+fn bytes_to_os_string(bytes: Vec<u8>) -> OsString {
+    OsString::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Raw accessors for a string field of an entry, for names that are not valid
+/// UTF-8 (Latin-1, Shift-JIS...). All of them return `None` when the field is unset.
+macro_rules! raw_string_getters {
+    ( $bytes:ident, $os:ident, $apiname:ident) => {
+        /// Field as stored in the archive.
+        pub fn $bytes(&self) -> Option<Vec<u8>> {
+            unsafe {
+                wrap_to_bytes($apiname(self.entry))
+            }
+        }
+
+        pub fn $os(&self) -> Option<OsString> {
+            self.$bytes().map(bytes_to_os_string)
+        }
+    };
+}
+
+macro_rules! is_set {
+    ( $fname:ident, $apiname:ident) => {
+        pub fn $fname(&self) -> bool {
+            unsafe {
+                (concat_idents!(archive_entry_, $apiname, _is_set))(self.entry) != 0
+            }
+        }
+    };
+}
+
+/// Getter converting a string field to UTF-8 independently of the process locale,
+/// `None` when unset or not convertible.
+macro_rules! utf8_getter {
+    ( $fname:ident, $apiname:ident) => {
+        pub fn $fname(&self) -> Option<String> {
+            unsafe {
+                wrap_to_option_string($apiname(self.entry))
+            }
+        }
+    };
+}
+
+/// Setter storing a string field as UTF-8 independently of the process locale.
+///
+/// The UTF-8 value is always stored and used by formats that record UTF-8 names
+/// (pax, zip, 7zip...). Returns `false` if it could not also be converted to the
+/// locale charset, which formats without charset information rely on.
+macro_rules! utf8_setter {
+    ( $fname:ident, $apiname:ident) => {
+        pub fn $fname(&mut self, value: &str) -> bool {
+            let c_value = CString::new(value).unwrap();
+            unsafe {
+                $apiname(self.entry, c_value.as_ptr()) != 0
+            }
+        }
+    };
+}
+
+/// Metadata getters shared by `ArchiveEntryReader` and `ArchiveEntry`.
+///
+/// Fields a format does not store read as zero; use the matching `*_is_set`
+/// predicate to tell them apart from a real zero.
+macro_rules! entry_getters {
+    () => {
+        pub fn size(&self) -> i64 {
+            unsafe {
+                archive_entry_size(self.entry)
+            }
+        }
+
+        /// Path of the entry, lossily converted to UTF-8; see `pathname_bytes` and `path`.
+        pub fn pathname(&self) -> Option<String> {
+            unsafe {
+                wrap_to_option_string(archive_entry_pathname(self.entry))
+            }
+        }
+
+        pub fn path(&self) -> Option<PathBuf> {
+            self.pathname_os().map(PathBuf::from)
+        }
+
+        pub fn symlink_path(&self) -> Option<PathBuf> {
+            self.symlink_os().map(PathBuf::from)
+        }
+
+        pub fn hardlink_path(&self) -> Option<PathBuf> {
+            self.hardlink_os().map(PathBuf::from)
+        }
+
+        // the plain getters (`pathname`, `symlink`...) already convert lossily
+        raw_string_getters!(pathname_bytes, pathname_os, archive_entry_pathname);
+        raw_string_getters!(symlink_bytes, symlink_os, archive_entry_symlink);
+        raw_string_getters!(hardlink_bytes, hardlink_os, archive_entry_hardlink);
+        raw_string_getters!(uname_bytes, uname_os, archive_entry_uname);
+        raw_string_getters!(gname_bytes, gname_os, archive_entry_gname);
+
+        utf8_getter!(pathname_utf8, archive_entry_pathname_utf8);
+        utf8_getter!(symlink_utf8, archive_entry_symlink_utf8);
+        utf8_getter!(hardlink_utf8, archive_entry_hardlink_utf8);
+        utf8_getter!(uname_utf8, archive_entry_uname_utf8);
+        utf8_getter!(gname_utf8, archive_entry_gname_utf8);
+
+        pub fn filetype(&self) -> FileType {
+            unsafe {
+                FileType::from_mode(archive_entry_filetype(self.entry) as u32)
+            }
+        }
+
+        /// File type and permissions, as in `st_mode`.
+        pub fn mode(&self) -> u32 {
+            unsafe {
+                archive_entry_mode(self.entry) as u32
+            }
+        }
+
+        pub fn perm(&self) -> u32 {
+            unsafe {
+                archive_entry_perm(self.entry) as u32
+            }
+        }
+
+        /// Mode rendered as by `ls -l`, e.g. `"-rw-r--r-- "`.
+        pub fn strmode(&self) -> String {
+            unsafe {
+                wrap_to_string(archive_entry_strmode(self.entry))
+            }
+        }
+
+        pub fn uid(&self) -> i64 {
+            unsafe {
+                archive_entry_uid(self.entry)
+            }
         }
-    }
+
+        pub fn gid(&self) -> i64 {
+            unsafe {
+                archive_entry_gid(self.entry)
+            }
+        }
+
+        pub fn uname(&self) -> Option<String> {
+            unsafe {
+                wrap_to_option_string(archive_entry_uname(self.entry))
+            }
+        }
+
+        pub fn gname(&self) -> Option<String> {
+            unsafe {
+                wrap_to_option_string(archive_entry_gname(self.entry))
+            }
+        }
+
+        /// Target of a hard link entry.
+        pub fn hardlink(&self) -> Option<String> {
+            unsafe {
+                wrap_to_option_string(archive_entry_hardlink(self.entry))
+            }
+        }
+
+        /// Target of a symbolic link entry.
+        pub fn symlink(&self) -> Option<String> {
+            unsafe {
+                wrap_to_option_string(archive_entry_symlink(self.entry))
+            }
+        }
+
+        pub fn nlink(&self) -> u32 {
+            unsafe {
+                archive_entry_nlink(self.entry) as u32
+            }
+        }
+
+        pub fn ino(&self) -> i64 {
+            unsafe {
+                archive_entry_ino64(self.entry)
+            }
+        }
+
+        /// Device the entry resides on.
+        pub fn dev(&self) -> u64 {
+            unsafe {
+                archive_entry_dev(self.entry) as u64
+            }
+        }
+
+        pub fn devmajor(&self) -> u64 {
+            unsafe {
+                archive_entry_devmajor(self.entry) as u64
+            }
+        }
+
+        pub fn devminor(&self) -> u64 {
+            unsafe {
+                archive_entry_devminor(self.entry) as u64
+            }
+        }
+
+        /// Device number of a character or block device entry.
+        pub fn rdev(&self) -> u64 {
+            unsafe {
+                archive_entry_rdev(self.entry) as u64
+            }
+        }
+
+        pub fn rdevmajor(&self) -> u64 {
+            unsafe {
+                archive_entry_rdevmajor(self.entry) as u64
+            }
+        }
+
+        pub fn rdevminor(&self) -> u64 {
+            unsafe {
+                archive_entry_rdevminor(self.entry) as u64
+            }
+        }
+
+        is_set!(size_is_set, size);
+        is_set!(ino_is_set, ino);
+        is_set!(dev_is_set, dev);
+        is_set!(access_time_is_set, atime);
+        is_set!(creation_time_is_set, birthtime);
+        is_set!(inode_change_time_is_set, ctime);
+        is_set!(modification_time_is_set, mtime);
+
+        get_time!(access_time, atime);
+        get_time!(creation_time, birthtime);
+        get_time!(inode_change_time, ctime);
+        get_time!(modification_time, mtime);
+    };
+}
+
+impl<'r> ArchiveEntryReader<'r> {
+    entry_getters!();
 
     pub fn sourcepath(&self) -> String {
         unsafe {
@@ -555,22 +1930,17 @@ impl ArchiveEntryReader {
         }
     }
 
-    pub fn set_filetype(&self, filetype: ArchiveEntryFiletype) {
-      let c_type = match filetype {
-        ArchiveEntryFiletype::AE_IFMT   => 0o170000,
-        ArchiveEntryFiletype::AE_IFREG  => 0o100000,
-        ArchiveEntryFiletype::AE_IFLNK  => 0o120000,
-        ArchiveEntryFiletype::AE_IFSOCK => 0o140000,
-        ArchiveEntryFiletype::AE_IFCHR  => 0o020000,
-        ArchiveEntryFiletype::AE_IFBLK  => 0o060000,
-        ArchiveEntryFiletype::AE_IFDIR  => 0o040000,
-        ArchiveEntryFiletype::AE_IFIFO  => 0o010000
-      };
+    pub fn set_filetype(&self, filetype: FileType) {
       unsafe {
-        archive_entry_set_filetype(self.entry, c_type);
+        archive_entry_set_filetype(self.entry, filetype.to_mode());
       }
     }
 
+    /// Copies this entry into an owned `ArchiveEntry` that outlives the reader position.
+    pub fn to_entry(&self) -> ArchiveEntry {
+        ArchiveEntry::from_raw(self.entry)
+    }
+
     pub fn set_pathname(&self, pathname: &str) {
       let c_pathname = CString::new(pathname).unwrap();
       unsafe {
@@ -578,8 +1948,59 @@ impl ArchiveEntryReader {
       }
     }
 
-    pub fn archive(&self) -> Reader {
-        Reader { handler: self.handler.clone() }
+    pub fn archive(&self) -> &Reader {
+        &*self.reader
+    }
+
+    fn handler(&self) -> *mut Struct_archive {
+        *self.reader.handler
+    }
+
+    fn check(&mut self, res: c_int) -> Result<(), ArchiveError> {
+        if let Some(warning) = try!(self.reader.check(res)) {
+            self.warnings.push(warning);
+        }
+        Ok(())
+    }
+
+    /// Streams the data of this entry.
+    pub fn data<'s>(&'s mut self) -> EntryData<'s, 'r> {
+        EntryData { entry: self }
+    }
+
+    /// Reads the next block of entry data without copying it.
+    ///
+    /// Returns `None` once the entry data is exhausted. Blocks of sparse entries
+    /// are not contiguous, use `DataBlock::offset` to place them; a trailing empty
+    /// block may be returned to mark the full size of the entry. `ARCHIVE_RETRY`
+    /// is returned as an error, as by `data`.
+    pub fn read_block<'s>(&'s mut self) -> Result<Option<DataBlock<'s>>, ArchiveError> {
+        unsafe {
+            let mut buff: *const c_void = ptr::null();
+            let mut size: size_t = 0;
+            let mut offset: int64_t = 0;
+            let res = archive_read_data_block(self.handler(), &mut buff, &mut size, &mut offset);
+            if res==ARCHIVE_EOF {
+                return Ok(None);
+            }
+            try!(self.check(res));
+            let data: &'s [u8] = if buff.is_null() {
+                &[]
+            } else {
+                std::slice::from_raw_parts(buff as *const u8, size as usize)
+            };
+            Ok(Some(DataBlock { data: data, offset: offset }))
+        }
+    }
+
+    /// Lending iterator over the data blocks of this entry, see `read_block`.
+    pub fn blocks<'s>(&'s mut self) -> Blocks<'s, 'r> {
+        Blocks { entry: self, done: false }
+    }
+
+    /// Warnings reported while reading this header, its data, or extracting the entry.
+    pub fn warnings(&self) -> &[ArchiveError] {
+        &self.warnings
     }
 
     pub fn extract_to(self, path : &str, flags : Vec<ArchiveExtractFlag>) -> Result<Self, ArchiveError> {
@@ -589,21 +2010,343 @@ impl ArchiveEntryReader {
             self.extract(flags)
         }
     }
-    pub fn extract(self,flags : Vec<ArchiveExtractFlag>) -> Result<Self, ArchiveError> {        
+    pub fn extract(mut self,flags : Vec<ArchiveExtractFlag>) -> Result<Self, ArchiveError> {        
         unsafe {
-          let res = archive_read_extract(*self.handler, self.entry, flags_to_code(flags));
-          if res==ARCHIVE_OK {
-              Ok(self)
-          } else {
-            Err(code_to_error(res))
-          }
+          let res = archive_read_extract(self.handler(), self.entry, flags_to_code(flags));
+          try!(self.check(res));
+          Ok(self)
         }
     }
+}
+
+mod sealed {
+    /// Keeps `AsArchiveEntry` from being implemented outside this crate, the
+    /// pointer it returns is handed to libarchive as is.
+    pub trait Sealed {}
+}
+
+/// Entries that can be passed to `Writer::write_header`.
+pub trait AsArchiveEntry: sealed::Sealed {
+    #[doc(hidden)]
+    fn entry_ptr(&self) -> *mut Struct_archive_entry;
+}
 
+impl<'r> sealed::Sealed for ArchiveEntryReader<'r> {}
 
-    get_time!(access_time, atime);
-    get_time!(creation_time, birthtime);
-    get_time!(inode_change_time, ctime);
-    get_time!(modification_time, mtime);
+impl<'r> AsArchiveEntry for ArchiveEntryReader<'r> {
+    fn entry_ptr(&self) -> *mut Struct_archive_entry {
+        self.entry
+    }
 }
 
+/// Archive entry owned by Rust, independent of any reader or writer.
+pub struct ArchiveEntry {
+    entry: *mut Struct_archive_entry
+}
+
+impl ArchiveEntry {
+    pub fn new() -> Result<ArchiveEntry, ArchiveError> {
+        unsafe {
+            let e = archive_entry_new();
+            if e.is_null() {
+                Err(ArchiveError::allocation())
+            } else {
+                Ok(ArchiveEntry { entry: e })
+            }
+        }
+    }
+
+    fn from_raw(entry: *mut Struct_archive_entry) -> ArchiveEntry {
+        unsafe {
+            let e = archive_entry_clone(entry);
+            if e.is_null() {
+                panic!("failed to allocate archive entry");
+            }
+            ArchiveEntry { entry: e }
+        }
+    }
+
+    entry_getters!();
+
+    pub fn set_pathname(&mut self, pathname: &str) {
+        let c_pathname = CString::new(pathname).unwrap();
+        unsafe {
+            archive_entry_copy_pathname(self.entry, c_pathname.as_ptr());
+        }
+    }
+
+    pub fn set_filetype(&mut self, filetype: FileType) {
+        unsafe {
+            archive_entry_set_filetype(self.entry, filetype.to_mode());
+        }
+    }
+
+    /// Sets file type and permissions at once, as in `st_mode`.
+    pub fn set_mode(&mut self, mode: u32) {
+        unsafe {
+            archive_entry_set_mode(self.entry, mode as mode_t);
+        }
+    }
+
+    pub fn set_perm(&mut self, perm: u32) {
+        unsafe {
+            archive_entry_set_perm(self.entry, perm as mode_t);
+        }
+    }
+
+    pub fn set_size(&mut self, size: i64) {
+        unsafe {
+            archive_entry_set_size(self.entry, size);
+        }
+    }
+
+    pub fn set_uid(&mut self, uid: i64) {
+        unsafe {
+            archive_entry_set_uid(self.entry, uid);
+        }
+    }
+
+    pub fn set_gid(&mut self, gid: i64) {
+        unsafe {
+            archive_entry_set_gid(self.entry, gid);
+        }
+    }
+
+    pub fn set_uname(&mut self, uname: &str) {
+        let c_uname = CString::new(uname).unwrap();
+        unsafe {
+            archive_entry_copy_uname(self.entry, c_uname.as_ptr());
+        }
+    }
+
+    pub fn set_gname(&mut self, gname: &str) {
+        let c_gname = CString::new(gname).unwrap();
+        unsafe {
+            archive_entry_copy_gname(self.entry, c_gname.as_ptr());
+        }
+    }
+
+    /// Target of a hard link entry.
+    pub fn set_hardlink(&mut self, target: &str) {
+        let c_target = CString::new(target).unwrap();
+        unsafe {
+            archive_entry_copy_hardlink(self.entry, c_target.as_ptr());
+        }
+    }
+
+    /// Target of a symbolic link entry.
+    pub fn set_symlink(&mut self, target: &str) {
+        let c_target = CString::new(target).unwrap();
+        unsafe {
+            archive_entry_copy_symlink(self.entry, c_target.as_ptr());
+        }
+    }
+
+    /// Device the entry resides on.
+    pub fn set_dev(&mut self, dev: u64) {
+        unsafe {
+            archive_entry_set_dev(self.entry, dev as dev_t);
+        }
+    }
+
+    /// Device number of a character or block device entry.
+    pub fn set_rdev(&mut self, rdev: u64) {
+        unsafe {
+            archive_entry_set_rdev(self.entry, rdev as dev_t);
+        }
+    }
+
+    pub fn set_rdevmajor(&mut self, major: u64) {
+        unsafe {
+            archive_entry_set_rdevmajor(self.entry, major as dev_t);
+        }
+    }
+
+    pub fn set_rdevminor(&mut self, minor: u64) {
+        unsafe {
+            archive_entry_set_rdevminor(self.entry, minor as dev_t);
+        }
+    }
+
+    set_time!(set_access_time, atime);
+    set_time!(set_creation_time, birthtime);
+    set_time!(set_inode_change_time, ctime);
+    set_time!(set_modification_time, mtime);
+
+    utf8_setter!(set_pathname_utf8, archive_entry_update_pathname_utf8);
+    utf8_setter!(set_symlink_utf8, archive_entry_update_symlink_utf8);
+    utf8_setter!(set_hardlink_utf8, archive_entry_update_hardlink_utf8);
+    utf8_setter!(set_uname_utf8, archive_entry_update_uname_utf8);
+    utf8_setter!(set_gname_utf8, archive_entry_update_gname_utf8);
+}
+
+impl Clone for ArchiveEntry {
+    fn clone(&self) -> ArchiveEntry {
+        ArchiveEntry::from_raw(self.entry)
+    }
+}
+
+impl Drop for ArchiveEntry {
+    fn drop(&mut self) {
+        unsafe { archive_entry_free(self.entry); }
+    }
+}
+
+impl sealed::Sealed for ArchiveEntry {}
+
+impl AsArchiveEntry for ArchiveEntry {
+    fn entry_ptr(&self) -> *mut Struct_archive_entry {
+        self.entry
+    }
+}
+
+macro_rules! build_with {
+    ( $fname:ident, $setter:ident, $argtype:ty) => {
+        pub fn $fname(mut self, value: $argtype) -> Self {
+            self.entry.$setter(value);
+            self
+        }
+    };
+}
+
+/// Same as `build_with!` for the `set_*_utf8` setters, remembering the fields that
+/// could not be converted to the locale charset, see `ArchiveEntryBuilder::unconverted`.
+macro_rules! build_with_utf8 {
+    ( $fname:ident, $setter:ident) => {
+        pub fn $fname(mut self, value: &str) -> Self {
+            if !self.entry.$setter(value) {
+                self.unconverted.push(stringify!($fname));
+            }
+            self
+        }
+    };
+}
+
+/// Builder for `ArchiveEntry`.
+///
+/// ```ignore
+/// let entry = try!(ArchiveEntryBuilder::new())
+///     .pathname("bin/tool")
+///     .filetype(FileType::RegularFile)
+///     .perm(0o755)
+///     .size(data.len() as i64)
+///     .build();
+/// try!(writer.write_header(&entry));
+/// ```
+pub struct ArchiveEntryBuilder {
+    entry: ArchiveEntry,
+    unconverted: Vec<&'static str>
+}
+
+impl ArchiveEntryBuilder {
+    pub fn new() -> Result<ArchiveEntryBuilder, ArchiveError> {
+        Ok(ArchiveEntryBuilder { entry: try!(ArchiveEntry::new()), unconverted: Vec::new() })
+    }
+
+    build_with!(pathname, set_pathname, &str);
+    build_with!(filetype, set_filetype, FileType);
+    build_with!(mode, set_mode, u32);
+    build_with!(perm, set_perm, u32);
+    build_with!(size, set_size, i64);
+    build_with!(uid, set_uid, i64);
+    build_with!(gid, set_gid, i64);
+    build_with!(uname, set_uname, &str);
+    build_with!(gname, set_gname, &str);
+    build_with!(hardlink, set_hardlink, &str);
+    build_with!(symlink, set_symlink, &str);
+    build_with!(dev, set_dev, u64);
+    build_with!(rdev, set_rdev, u64);
+    build_with!(rdevmajor, set_rdevmajor, u64);
+    build_with!(rdevminor, set_rdevminor, u64);
+    build_with!(access_time, set_access_time, Timespec);
+    build_with!(creation_time, set_creation_time, Timespec);
+    build_with!(inode_change_time, set_inode_change_time, Timespec);
+    build_with!(modification_time, set_modification_time, Timespec);
+    build_with_utf8!(pathname_utf8, set_pathname_utf8);
+    build_with_utf8!(symlink_utf8, set_symlink_utf8);
+    build_with_utf8!(hardlink_utf8, set_hardlink_utf8);
+    build_with_utf8!(uname_utf8, set_uname_utf8);
+    build_with_utf8!(gname_utf8, set_gname_utf8);
+
+    /// Names of the `*_utf8` setters whose value could not be converted to the
+    /// locale charset.
+    ///
+    /// Formats recording UTF-8 names (pax, zip...) still store these values, those
+    /// without charset information (ustar, cpio...) will fail to write them.
+    pub fn unconverted(&self) -> &[&'static str] {
+        &self.unconverted
+    }
+
+    pub fn build(self) -> ArchiveEntry {
+        self.entry
+    }
+}
+
+/// `std::io::Read` over the data of an entry, see `ArchiveEntryReader::data`.
+///
+/// Warnings allowed by the reader policy are recorded on the entry, any other
+/// status fails the read.
+pub struct EntryData<'a, 'r: 'a> {
+    entry: &'a mut ArchiveEntryReader<'r>
+}
+
+impl<'a, 'r> Read for EntryData<'a, 'r> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let res = unsafe {
+                archive_read_data(self.entry.handler(), buf.as_mut_ptr() as *mut c_void, buf.len() as size_t)
+            };
+            if res >= 0 {
+                return Ok(res as usize);
+            }
+            // ARCHIVE_RETRY is an error too, retrying would not get past it
+            try!(self.entry.check(res as c_int));
+        }
+    }
+}
+
+/// Block of entry data borrowed from libarchive's internal buffer.
+///
+/// The data stays valid until the next read from the archive. The block borrows
+/// the entry, which holds the only borrow of its `Reader`, so nothing can read
+/// from the archive while the block is alive.
+pub struct DataBlock<'a> {
+    data: &'a [u8],
+    offset: i64
+}
+
+impl<'a> DataBlock<'a> {
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Position of the block within the entry.
+    pub fn offset(&self) -> i64 {
+        self.offset
+    }
+}
+
+/// Lending iterator over entry data blocks, see `ArchiveEntryReader::blocks`.
+///
+/// Each block borrows the iterator, so it is consumed with
+/// `while let Some(block) = blocks.next_block() { ... }`.
+pub struct Blocks<'a, 'r: 'a> {
+    entry: &'a mut ArchiveEntryReader<'r>,
+    done: bool
+}
+
+impl<'a, 'r> Blocks<'a, 'r> {
+    pub fn next_block<'s>(&'s mut self) -> Option<Result<DataBlock<'s>, ArchiveError>> {
+        if self.done {
+            return None;
+        }
+        match self.entry.read_block() {
+            Ok(Some(block)) => Some(Ok(block)),
+            Ok(None) => { self.done = true; None }
+            Err(err) => { self.done = true; Some(Err(err)) }
+        }
+    }
+}
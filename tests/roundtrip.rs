@@ -0,0 +1,151 @@
+extern crate Archive;
+
+use Archive::*;
+use std::io::{ErrorKind, Read, Write};
+
+#[test]
+fn write_then_read_back() {
+    let files = [
+        ("hello.txt", &b"hello world\n"[..]),
+        ("dir/empty", &b""[..]),
+        ("data.bin", &[0u8, 1, 2, 255, 254][..])
+    ];
+
+    let mut writer = Writer::new().unwrap().set_format(ArchiveFormat::Pax).unwrap();
+    writer.open_vec().unwrap();
+    for &(name, data) in files.iter() {
+        let entry = ArchiveEntryBuilder::new().unwrap()
+            .pathname(name)
+            .filetype(FileType::RegularFile)
+            .perm(0o644)
+            .size(data.len() as i64)
+            .build();
+        writer.write_header(&entry).unwrap();
+        assert_eq!(writer.write_data(data).unwrap(), data.len());
+        writer.write_finish_entry().unwrap();
+    }
+    let mut bytes = writer.into_vec().unwrap();
+
+    let mut reader = Reader::new().unwrap()
+        .support_filter_all()
+        .support_format_all()
+        .open_memory(&mut bytes).unwrap();
+    let mut entries = reader.entries();
+    for &(name, data) in files.iter() {
+        let mut entry = entries.next_entry().expect("missing entry").unwrap();
+        assert_eq!(entry.pathname(), Some(name.to_string()));
+        assert_eq!(entry.size(), data.len() as i64);
+        assert!(entry.filetype().is_file());
+        let mut content = Vec::new();
+        entry.data().read_to_end(&mut content).unwrap();
+        assert_eq!(&content[..], data);
+    }
+    assert!(entries.next_entry().is_none());
+}
+
+#[test]
+fn open_memory_rejects_empty_buffer() {
+    let mut memory: [u8; 0] = [];
+    let mut writer = Writer::new().unwrap().set_format(ArchiveFormat::Pax).unwrap();
+    assert!(writer.open_memory(&mut memory).is_err());
+}
+
+struct FailingSink;
+
+impl Write for FailingSink {
+    fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+        Err(std::io::Error::new(ErrorKind::BrokenPipe, "sink closed"))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn sink_error_is_kept() {
+    let data = vec![0u8; 1 << 20];
+    let entry = ArchiveEntryBuilder::new().unwrap()
+        .pathname("big.bin")
+        .filetype(FileType::RegularFile)
+        .perm(0o644)
+        .size(data.len() as i64)
+        .build();
+
+    let mut writer = Writer::new().unwrap().set_format(ArchiveFormat::Pax).unwrap();
+    writer.open_stream(FailingSink).unwrap();
+    writer.write_header(&entry).unwrap();
+    // the data is larger than a block, so the sink is called before close at the latest
+    let err = match writer.write_data(&data) {
+        Err(err) => err,
+        Ok(_) => writer.close().unwrap_err()
+    };
+    assert_eq!(err.io_error().map(|cause| cause.kind()), Some(ErrorKind::BrokenPipe));
+}
+
+#[test]
+fn write_into_fixed_buffer() {
+    let data = &b"fixed buffer\n"[..];
+    let mut memory = vec![0u8; 64 * 1024];
+    let used = {
+        let entry = ArchiveEntryBuilder::new().unwrap()
+            .pathname("fixed.txt")
+            .filetype(FileType::RegularFile)
+            .perm(0o644)
+            .size(data.len() as i64)
+            .build();
+        let mut writer = Writer::new().unwrap().set_format(ArchiveFormat::Pax).unwrap();
+        writer.open_memory(&mut memory).unwrap();
+        writer.write_header(&entry).unwrap();
+        writer.write_data(data).unwrap();
+        writer.write_finish_entry().unwrap();
+        writer.close_memory().unwrap()
+    };
+    assert!(used > 0 && used <= memory.len());
+
+    let mut reader = Reader::new().unwrap()
+        .support_format_all()
+        .open_memory(&mut memory[..used]).unwrap();
+    let mut entries = reader.entries();
+    {
+        let mut entry = entries.next_entry().expect("missing entry").unwrap();
+        assert_eq!(entry.pathname(), Some("fixed.txt".to_string()));
+        let mut content = Vec::new();
+        entry.data().read_to_end(&mut content).unwrap();
+        assert_eq!(&content[..], data);
+    }
+    assert!(entries.next_entry().is_none());
+}
+
+#[test]
+fn utf8_pathname_survives_pax() {
+    // whatever `unconverted` reports for the test locale, pax stores the UTF-8 name
+    let entry = ArchiveEntryBuilder::new().unwrap()
+        .pathname_utf8("café/naïve.txt")
+        .filetype(FileType::RegularFile)
+        .perm(0o644)
+        .size(0)
+        .build();
+
+    let mut writer = Writer::new().unwrap().set_format(ArchiveFormat::Pax).unwrap();
+    writer.open_vec().unwrap();
+    writer.write_header(&entry).unwrap();
+    writer.write_finish_entry().unwrap();
+    let mut bytes = writer.into_vec().unwrap();
+
+    let mut reader = Reader::new().unwrap()
+        .support_format_all()
+        .open_memory(&mut bytes).unwrap();
+    let mut entries = reader.entries();
+    {
+        let entry = entries.next_entry().expect("missing entry").unwrap();
+        assert_eq!(entry.pathname_utf8(), Some("café/naïve.txt".to_string()));
+    }
+    assert!(entries.next_entry().is_none());
+}
+
+#[test]
+fn options_with_nul_byte_are_rejected() {
+    assert!(Reader::new().unwrap().set_options("zip:hdrcharset=CP437\0").is_err());
+    assert!(Writer::new().unwrap().set_option(None, "key\0", "value").is_err());
+}